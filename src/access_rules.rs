@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::account_map::{describe, glob_match};
+use crate::authorization::{resolve_name, AuthorizationRules};
+use crate::claims::VerifiedClaims;
+
+/// Outcome of the rule that matched a login.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Continue with the remaining authorization checks.
+    Allow,
+    #[default]
+    Deny,
+    /// Return `PAM_IGNORE` so the rest of the PAM stack decides.
+    Ignore,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Allow => "allowed",
+            Action::Deny => "denied",
+            Action::Ignore => "ignored",
+        })
+    }
+}
+
+/// Where a login happens, read from the PAM handle and the system.
+#[derive(Debug, Clone, Default)]
+pub struct LoginContext {
+    /// `PAM_SERVICE`, e.g. `sshd` or `sudo`.
+    pub service: Option<String>,
+    pub hostname: Option<String>,
+    /// `PAM_RHOST`, an address or a name depending on the application.
+    pub rhost: Option<String>,
+}
+
+impl LoginContext {
+    pub fn local_hostname() -> Option<String> {
+        nix::unistd::gethostname().ok()?.into_string().ok()
+    }
+}
+
+impl fmt::Display for LoginContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+        write!(
+            f,
+            "service {}, host {}, rhost {}",
+            unknown(&self.service),
+            unknown(&self.hostname),
+            unknown(&self.rhost)
+        )
+    }
+}
+
+/// One entry of the access rules file. Every list that is set must match,
+/// an empty list matches anything. All but `rhosts` take `*` and `?`
+/// wildcards.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessRule {
+    pub name: String,
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// Patterns for the local hostname, compared case-insensitively.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Local target accounts.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Addresses or CIDR networks `PAM_RHOST` must be in.
+    #[serde(default)]
+    pub rhosts: Vec<String>,
+    /// Remote groups, of which the user must be in one. Friendly names from
+    /// `authorization.names` are resolved.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Remote usernames.
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    pub action: Action,
+}
+
+impl AccessRule {
+    fn matches(
+        &self,
+        claims: &VerifiedClaims,
+        local_user: &str,
+        context: &LoginContext,
+        names: &BTreeMap<String, String>,
+    ) -> bool {
+        let any = |patterns: &[String], value: Option<&str>| {
+            patterns.is_empty() || value.is_some_and(|v| patterns.iter().any(|p| glob_match(p, v)))
+        };
+        let hostname = context.hostname.as_deref().map(str::to_lowercase);
+        let host_matches = self.hosts.is_empty()
+            || hostname.as_deref().is_some_and(|h| {
+                self.hosts.iter().any(|p| glob_match(&p.to_lowercase(), h))
+            });
+        let groups = claims.groups().unwrap_or_default();
+        let group_matches = |p: &String| {
+            let p = resolve_name(names, p);
+            groups.iter().any(|g| glob_match(p, g))
+        };
+
+        any(&self.services, context.service.as_deref())
+            && host_matches
+            && any(&self.users, Some(local_user))
+            && (self.rhosts.is_empty() || self.rhost_matches(context.rhost.as_deref()))
+            && (self.groups.is_empty() || self.groups.iter().any(group_matches))
+            && any(&self.usernames, Some(claims.username()))
+    }
+
+    // A remote host given by name never matches a network
+    fn rhost_matches(&self, rhost: Option<&str>) -> bool {
+        let Some(addr) = rhost.and_then(|r| r.parse::<IpAddr>().ok()) else {
+            return false;
+        };
+        self.networks().any(|net| net.contains(&addr))
+    }
+
+    fn networks(&self) -> impl Iterator<Item = IpNet> + '_ {
+        self.rhosts.iter().filter_map(|r| parse_network(r).ok())
+    }
+}
+
+/// Contents of the `access_rules` file: rules tried in order, the first
+/// match decides.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccessRules {
+    /// Action when no rule matches.
+    #[serde(default)]
+    pub default: Action,
+    #[serde(default)]
+    pub rules: Vec<AccessRule>,
+    #[serde(skip)]
+    names: BTreeMap<String, String>,
+}
+
+impl AccessRules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let rules: Self = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for rule in &rules.rules {
+            for rhost in &rule.rhosts {
+                parse_network(rhost)
+                    .with_context(|| format!("Invalid rhost '{}' in rule '{}'", rhost, rule.name))?;
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Resolves group names through the same `names` as `authorization`.
+    pub fn with_names(mut self, authorization: &AuthorizationRules) -> Self {
+        self.names = authorization.names.clone();
+        self
+    }
+
+    pub fn uses_groups(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.groups.is_empty())
+    }
+
+    /// Action of the first rule matching the login, logged with its name.
+    pub fn evaluate(&self, claims: &VerifiedClaims, local_user: &str, context: &LoginContext) -> Action {
+        let remote = describe(claims);
+        match self.rules.iter().find(|r| r.matches(claims, local_user, context, &self.names)) {
+            Some(rule) => {
+                log::info!(
+                    "Access rule '{}': {} -> {} {} ({})",
+                    rule.name,
+                    remote,
+                    local_user,
+                    rule.action,
+                    context
+                );
+                rule.action
+            }
+            None => {
+                log::info!(
+                    "Access rules: {} -> {} {} by default, no rule matched ({})",
+                    remote,
+                    local_user,
+                    self.default,
+                    context
+                );
+                self.default
+            }
+        }
+    }
+}
+
+// A single address is a network of one
+fn parse_network(value: &str) -> Result<IpNet, ipnet::AddrParseError> {
+    value
+        .parse::<IpNet>()
+        .or_else(|err| value.parse::<IpAddr>().map(IpNet::from).map_err(|_| err))
+}
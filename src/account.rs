@@ -0,0 +1,80 @@
+use serde_json::Value;
+
+use crate::access_rules::Action;
+use crate::claims::VerifiedClaims;
+use crate::config::Config;
+
+/// Key under which `sm_authenticate` leaves its `AuthDecision` in the PAM
+/// handle.
+pub const DECISION_KEY: &str = "pam_oauth2_device_decision";
+
+/// Outcome of `sm_authenticate`, kept with `pam_set_data` so that
+/// `acct_mgmt` enforces it for the same user in the same PAM transaction.
+#[derive(Debug, Clone)]
+pub struct AuthDecision {
+    pub local_user: String,
+    pub claims: VerifiedClaims,
+    /// Combined result of the access rules, `allowed_groups`, `authorization`
+    /// and `account_map`.
+    pub policy: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Valid,
+    Denied,
+    /// An access rule left the decision to the rest of the stack.
+    Ignored,
+    /// The token or, with `account_expiry_claim`, the account at the
+    /// provider has expired.
+    Expired,
+    /// The provider reports the password as expired (`pwd_exp`).
+    PasswordExpired,
+}
+
+impl AuthDecision {
+    /// Account state at `now`, in seconds since the epoch.
+    pub fn status(&self, config: &Config, now: i64) -> AccountStatus {
+        match self.policy {
+            Action::Allow => {}
+            Action::Deny => return AccountStatus::Denied,
+            Action::Ignore => return AccountStatus::Ignored,
+        }
+        let username = self.claims.username();
+        let leeway = config.claim_requirements.leeway.as_secs() as i64;
+
+        if let Some(exp) = self.claims.exp() {
+            if exp + leeway < now {
+                log::warn!("Token of {} expired at {}", username, exp);
+                return AccountStatus::Expired;
+            }
+        }
+        if let Some(claim) = &config.account_expiry_claim {
+            if let Some(expiry) = self.claims.lookup(claim).and_then(seconds) {
+                if expiry <= now {
+                    log::warn!("Account of {} expired at {} ('{}')", username, expiry, claim);
+                    return AccountStatus::Expired;
+                }
+            }
+        }
+        // Azure AD counts `pwd_exp` in seconds from `iat`
+        let iat = self.claims.get("iat").and_then(seconds);
+        let pwd_exp = self.claims.get("pwd_exp").and_then(seconds);
+        if let (Some(iat), Some(pwd_exp)) = (iat, pwd_exp) {
+            if iat + pwd_exp <= now {
+                log::warn!("Password of {} expired at {}", username, iat + pwd_exp);
+                return AccountStatus::PasswordExpired;
+            }
+        }
+        AccountStatus::Valid
+    }
+}
+
+// Providers send timestamps as numbers or as numeric strings
+fn seconds(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
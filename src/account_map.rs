@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::claims::VerifiedClaims;
+
+/// What happens to a local account that no entry of the map covers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmappedAccounts {
+    /// The remote username must equal the local account name.
+    #[default]
+    SameName,
+    Deny,
+}
+
+/// Remote identities allowed to log in as one local account. Every entry may
+/// contain `*` and `?` wildcards.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccountRule {
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl AccountRule {
+    /// Describes the first identity of `claims` this rule accepts.
+    fn matches(&self, claims: &VerifiedClaims) -> Option<String> {
+        if let Some(sub) = claims.sub() {
+            if let Some(p) = self.subjects.iter().find(|p| glob_match(p, sub)) {
+                return Some(format!("subject {} matches '{}'", sub, p));
+            }
+        }
+        if let Some(p) = self.usernames.iter().find(|p| glob_match(p, claims.username())) {
+            return Some(format!("username {} matches '{}'", claims.username(), p));
+        }
+        for group in claims.groups().unwrap_or_default() {
+            if let Some(p) = self.groups.iter().find(|p| glob_match(p, group)) {
+                return Some(format!("group {} matches '{}'", group, p));
+            }
+        }
+        None
+    }
+}
+
+/// Contents of the `account_map` file: local account name (or wildcard
+/// pattern) to the remote identities that may use it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccountMap {
+    #[serde(default)]
+    pub unmapped: UnmappedAccounts,
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AccountRule>,
+}
+
+impl AccountMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        serde_json::from_reader(file).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn uses_groups(&self) -> bool {
+        self.accounts.values().any(|rule| !rule.groups.is_empty())
+    }
+
+    /// Decides whether the verified identity may log in as `local_user` and
+    /// logs the decision either way.
+    pub fn authorize(&self, claims: &VerifiedClaims, local_user: &str) -> bool {
+        let remote = describe(claims);
+        if local_user == "root" {
+            log::warn!("Account map: refusing {} -> root", remote);
+            return false;
+        }
+
+        // The exact entry first, so its decision is the one that gets logged
+        let rules = self
+            .accounts
+            .get_key_value(local_user)
+            .into_iter()
+            .chain(
+                self.accounts
+                    .iter()
+                    .filter(|(pattern, _)| *pattern != local_user && glob_match(pattern, local_user)),
+            )
+            .collect::<Vec<_>>();
+
+        for (pattern, rule) in &rules {
+            if let Some(reason) = rule.matches(claims) {
+                log::info!(
+                    "Account map: {} -> {} allowed by entry '{}' ({})",
+                    remote,
+                    local_user,
+                    pattern,
+                    reason
+                );
+                return true;
+            }
+        }
+
+        if !rules.is_empty() {
+            log::warn!(
+                "Account map: {} -> {} denied, no match in entries {:?}",
+                remote,
+                local_user,
+                rules.iter().map(|(p, _)| p).collect::<Vec<_>>()
+            );
+            return false;
+        }
+
+        match self.unmapped {
+            UnmappedAccounts::SameName if claims.username() == local_user => {
+                log::info!("Account map: {} -> {} allowed by same name", remote, local_user);
+                true
+            }
+            UnmappedAccounts::SameName => {
+                log::warn!(
+                    "Account map: {} -> {} denied, account is not mapped and names differ",
+                    remote,
+                    local_user
+                );
+                false
+            }
+            UnmappedAccounts::Deny => {
+                log::warn!("Account map: {} -> {} denied, account is not mapped", remote, local_user);
+                false
+            }
+        }
+    }
+}
+
+pub(crate) fn describe(claims: &VerifiedClaims) -> String {
+    match claims.sub() {
+        Some(sub) => format!("{} (sub {})", claims.username(), sub),
+        None => claims.username().to_string(),
+    }
+}
+
+/// Shell-style matching where `*` is any run of characters and `?` exactly one.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let v: Vec<char> = value.chars().collect();
+    let (mut pi, mut vi) = (0, 0);
+    let mut backtrack = None;
+
+    while vi < v.len() {
+        match p.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, vi));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == v[vi] => {
+                pi += 1;
+                vi += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    pi = star + 1;
+                    vi = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::claims::VerifiedClaims;
+
+/// Authorization on claim values, checked together with `allowed_groups`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuthorizationRules {
+    /// Friendly names for the IDs used in `rules` and `allowed_groups`, e.g.
+    /// `"linux-admins": "a2b4c6d8-..."`.
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+
+    /// Whether any rule or all rules must match.
+    #[serde(default)]
+    pub require: Require,
+
+    #[serde(default)]
+    pub rules: Vec<AuthorizationRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Require {
+    #[default]
+    Any,
+    All,
+}
+
+/// Values one claim must contain. With neither `any_of` nor `all_of` the
+/// claim only has to be present.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthorizationRule {
+    /// Claim name such as `roles` or `wids`, or a JSON pointer into the
+    /// claims such as `/realm_access/roles`. `groups` includes groups
+    /// resolved through Microsoft Graph.
+    pub claim: String,
+
+    #[serde(default)]
+    pub any_of: Vec<String>,
+
+    #[serde(default)]
+    pub all_of: Vec<String>,
+}
+
+impl AuthorizationRules {
+    /// The ID behind a friendly name, or `value` itself.
+    pub fn resolve<'a>(&'a self, value: &'a str) -> &'a str {
+        resolve_name(&self.names, value)
+    }
+
+    /// Whether a rule needs the user's groups, which may have to be looked up
+    /// when the token leaves them out.
+    pub fn uses_groups(&self) -> bool {
+        self.rules.iter().any(AuthorizationRule::is_groups)
+    }
+
+    pub fn authorize(&self, claims: &VerifiedClaims) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        // Every rule is checked, so each one shows up in the log
+        let results: Vec<bool> = self.rules.iter().map(|rule| self.check(rule, claims)).collect();
+        let authorized = match self.require {
+            Require::Any => results.contains(&true),
+            Require::All => !results.contains(&false),
+        };
+        if !authorized {
+            log::warn!(
+                "User {} does not satisfy {} authorization rules. Access denied.",
+                claims.username(),
+                if self.require == Require::All { "all" } else { "any" }
+            );
+        }
+        authorized
+    }
+
+    fn check(&self, rule: &AuthorizationRule, claims: &VerifiedClaims) -> bool {
+        let values = match rule.values(claims) {
+            Some(values) => values,
+            None => {
+                log::info!("Authorization rule on '{}': claim missing", rule.claim);
+                return false;
+            }
+        };
+        let contains = |v: &String| values.iter().any(|value| value == self.resolve(v));
+        let matched = (rule.any_of.is_empty() || rule.any_of.iter().any(contains))
+            && rule.all_of.iter().all(contains);
+        log::info!(
+            "Authorization rule on '{}': {} (values {:?}, any_of {:?}, all_of {:?})",
+            rule.claim,
+            if matched { "matched" } else { "not matched" },
+            values,
+            rule.any_of,
+            rule.all_of
+        );
+        matched
+    }
+}
+
+impl AuthorizationRule {
+    fn is_groups(&self) -> bool {
+        self.claim == "groups" || self.claim == "/groups"
+    }
+
+    // A single value counts as a one-element list
+    fn values(&self, claims: &VerifiedClaims) -> Option<Vec<String>> {
+        if self.is_groups() {
+            return claims.groups().map(<[String]>::to_vec);
+        }
+        let value = claims.lookup(&self.claim)?;
+        let to_string = |v: &Value| match v {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(_) | Value::Bool(_) => Some(v.to_string()),
+            _ => None,
+        };
+        match value {
+            Value::Array(values) => Some(values.iter().filter_map(to_string).collect()),
+            value => to_string(value).map(|v| vec![v]),
+        }
+    }
+}
+
+/// The ID `names` gives a friendly name, or `value` itself.
+pub(crate) fn resolve_name<'a>(names: &'a BTreeMap<String, String>, value: &'a str) -> &'a str {
+    names.get(value).map_or(value, String::as_str)
+}
@@ -0,0 +1,98 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+/// Small JSON file cache shared by every process that loads the module.
+///
+/// Entries are replaced by writing a temporary file and renaming it over the
+/// old one, so concurrent readers only ever see a complete entry.
+#[derive(Debug, Clone)]
+pub struct FileCache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry<T> {
+    pub fetched_at: u64,
+    pub expires_at: u64,
+    pub value: T,
+}
+
+impl<T> CacheEntry<T> {
+    pub fn new(value: T, ttl: Duration) -> Self {
+        let now = now();
+        Self {
+            fetched_at: now,
+            expires_at: now.saturating_add(ttl.as_secs()),
+            value,
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        now() < self.expires_at
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+}
+
+impl FileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn read<T: DeserializeOwned>(&self, key: &str) -> Option<CacheEntry<T>> {
+        let path = self.dir.join(file_name(key));
+        let mut buff = String::new();
+        File::open(&path).ok()?.read_to_string(&mut buff).ok()?;
+        match serde_json::from_str(&buff) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                log::warn!("Ignoring corrupt cache file {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn write<T: Serialize>(&self, key: &str, entry: &CacheEntry<T>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(file_name(key));
+        let tmp_path = self
+            .dir
+            .join(format!(".{}.{}.tmp", file_name(key), std::process::id()));
+
+        let mut tmp_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        tmp_file.write_all(&serde_json::to_vec(entry)?)?;
+        tmp_file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+// Cache keys are usually URLs, so keep only characters that are safe in a file name.
+fn file_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}.json", name)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,245 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::username::{self, UsernameTransform};
+
+/// Claims of a token that passed validation, either a JWT whose signature,
+/// issuer, audience and lifetime were verified or an active introspection
+/// response. It can only be built by the validators in `oauth_device`, so
+/// everything read from it is trusted.
+#[derive(Debug, Clone)]
+pub struct VerifiedClaims {
+    username: String,
+    groups: Option<Vec<String>>,
+    claims: Map<String, Value>,
+}
+
+impl VerifiedClaims {
+    /// `username_claims` are tried in order and the first present one is run
+    /// through `transforms` to get the username.
+    pub(crate) fn new(
+        claims: Map<String, Value>,
+        username_claims: &[String],
+        transforms: &[UsernameTransform],
+    ) -> Result<Self> {
+        let remote_username = username_claims
+            .iter()
+            .find_map(|name| match claims.get(name) {
+                Some(Value::String(s)) => Some(s.clone()),
+                Some(Value::Number(n)) => Some(n.to_string()),
+                _ => None,
+            })
+            .context("No username provided in token")?;
+        let username = username::transform(&remote_username, transforms)?;
+
+        let groups = match claims.get("groups") {
+            None | Some(Value::Null) => None,
+            Some(groups) => Some(
+                serde_json::from_value(groups.clone()).context("Malformed 'groups' claim in token")?,
+            ),
+        };
+
+        Ok(Self {
+            username,
+            groups,
+            claims,
+        })
+    }
+
+    /// Claims verified earlier and kept where only the module can change
+    /// them, such as a grace period entry.
+    pub(crate) fn restore(
+        username: String,
+        groups: Option<Vec<String>>,
+        claims: Map<String, Value>,
+    ) -> Self {
+        Self {
+            username,
+            groups,
+            claims,
+        }
+    }
+
+    pub(crate) fn claims(&self) -> &Map<String, Value> {
+        &self.claims
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn groups(&self) -> Option<&[String]> {
+        self.groups.as_deref()
+    }
+
+    /// Group membership looked up outside the token.
+    pub(crate) fn set_groups(&mut self, groups: Vec<String>) {
+        self.groups = Some(groups);
+    }
+
+    pub fn sub(&self) -> Option<&str> {
+        self.get_str("sub")
+    }
+
+    /// Expiration time as seconds since the epoch.
+    pub fn exp(&self) -> Option<i64> {
+        self.get("exp").and_then(Value::as_i64)
+    }
+
+    pub fn get(&self, claim: &str) -> Option<&Value> {
+        self.claims.get(claim)
+    }
+
+    pub fn get_str(&self, claim: &str) -> Option<&str> {
+        self.get(claim).and_then(Value::as_str)
+    }
+
+    /// Value at a JSON pointer (RFC 6901) into the claims, such as
+    /// `/realm_access/roles`.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        let path = pointer.strip_prefix('/')?;
+        let (name, rest) = match path.split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        let value = self.get(&name.replace("~1", "/").replace("~0", "~"))?;
+        match rest {
+            Some(rest) => value.pointer(&format!("/{}", rest)),
+            None => Some(value),
+        }
+    }
+
+    /// A claim by name, or by JSON pointer when it starts with `/`.
+    pub fn lookup(&self, claim: &str) -> Option<&Value> {
+        if claim.starts_with('/') {
+            self.pointer(claim)
+        } else {
+            self.get(claim)
+        }
+    }
+}
+
+/// Checks applied to every verified token on top of signature, issuer,
+/// audience and lifetime.
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClaimRequirements {
+    /// Clock skew tolerated when checking `exp` and `nbf`.
+    #[serde(default = "default_leeway")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub leeway: Duration,
+
+    /// Accepted `acr` values.
+    #[serde(default)]
+    pub acr: Vec<String>,
+
+    /// Methods that must all be listed in `amr`. When `acr` is also set,
+    /// satisfying either one is enough.
+    #[serde(default)]
+    pub amr: Vec<String>,
+
+    #[serde(default)]
+    pub email_verified: bool,
+
+    /// Claims that must be present. `null` accepts any value, anything else
+    /// must be equal or, for array claims, contained in the array.
+    #[serde(default)]
+    pub required: Map<String, Value>,
+}
+
+impl Default for ClaimRequirements {
+    fn default() -> Self {
+        Self {
+            leeway: default_leeway(),
+            acr: Vec::new(),
+            amr: Vec::new(),
+            email_verified: false,
+            required: Map::new(),
+        }
+    }
+}
+
+impl ClaimRequirements {
+    pub fn check(&self, claims: &VerifiedClaims) -> Result<()> {
+        self.check_authentication(claims)?;
+
+        if self.email_verified && claims.get("email_verified") != Some(&Value::Bool(true)) {
+            bail!(
+                "Claim 'email_verified' is {}, expected true",
+                describe(claims.get("email_verified"))
+            );
+        }
+
+        for (name, expected) in &self.required {
+            let actual = claims.get(name);
+            let satisfied = match (actual, expected) {
+                (None, _) => false,
+                (Some(_), Value::Null) => true,
+                (Some(Value::Array(values)), expected) if !expected.is_array() => {
+                    values.contains(expected)
+                }
+                (Some(actual), expected) => actual == expected,
+            };
+            if !satisfied {
+                bail!(
+                    "Claim '{}' is {}, expected {}",
+                    name,
+                    describe(actual),
+                    if expected.is_null() { "to be present".to_string() } else { expected.to_string() }
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // `acr` and `amr` are alternative ways for providers to express MFA.
+    fn check_authentication(&self, claims: &VerifiedClaims) -> Result<()> {
+        if self.acr.is_empty() && self.amr.is_empty() {
+            return Ok(());
+        }
+
+        let acr = claims.get_str("acr");
+        if acr.is_some_and(|acr| self.acr.iter().any(|a| a == acr)) {
+            return Ok(());
+        }
+
+        let amr: Vec<&str> = match claims.get("amr") {
+            Some(Value::Array(methods)) => methods.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !self.amr.is_empty() && self.amr.iter().all(|m| amr.contains(&m.as_str())) {
+            return Ok(());
+        }
+
+        match (self.acr.is_empty(), self.amr.is_empty()) {
+            (false, true) => bail!(
+                "Claim 'acr' is {}, expected one of {:?}",
+                describe(claims.get("acr")),
+                self.acr
+            ),
+            (true, false) => bail!(
+                "Claim 'amr' is {}, expected to contain {:?}",
+                describe(claims.get("amr")),
+                self.amr
+            ),
+            _ => bail!(
+                "Claim 'acr' is {}, expected one of {:?}, and claim 'amr' is {}, expected to contain {:?}",
+                describe(claims.get("acr")),
+                self.acr,
+                describe(claims.get("amr")),
+                self.amr
+            ),
+        }
+    }
+}
+
+fn describe(value: Option<&Value>) -> String {
+    value.map_or_else(|| "missing".to_string(), Value::to_string)
+}
+
+fn default_leeway() -> Duration {
+    Duration::from_secs(60)
+}
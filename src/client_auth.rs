@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::Config;
+
+pub const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+// Lifetime of a client assertion. It is signed right before each request.
+const ASSERTION_LIFETIME: i64 = 300;
+
+/// How the module authenticates to the token, device authorization and
+/// introspection endpoints. Names follow the OpenID Connect client
+/// registration values.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMethod {
+    /// `client_secret` in an HTTP Basic `Authorization` header.
+    ClientSecretBasic,
+    /// `client_secret` in the form body.
+    ClientSecretPost,
+    /// A JWT signed with `client_assertion_key` (RFC 7523).
+    PrivateKeyJwt,
+    /// The `client_certificate` presented in the TLS handshake, issued by a
+    /// CA the server trusts (RFC 8705, section 2.1).
+    TlsClientAuth,
+    /// The `client_certificate` presented in the TLS handshake, registered
+    /// with the server (RFC 8705, section 2.2).
+    SelfSignedTlsClientAuth,
+    /// Public client, only `client_id` is sent (RFC 8628, section 3.1).
+    None,
+}
+
+#[derive(Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    jti: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Signing key and header of the `private_key_jwt` client assertion.
+#[derive(Clone)]
+pub struct ClientAssertion {
+    client_id: String,
+    key: EncodingKey,
+    header: Header,
+}
+
+impl std::fmt::Debug for ClientAssertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientAssertion")
+            .field("client_id", &self.client_id)
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientAssertion {
+    /// Loads the key from `client_assertion_key`. A certificate, found either
+    /// in `client_assertion_certificate` or next to the key, adds its
+    /// thumbprints to the header, as Azure AD requires.
+    pub fn load(config: &Config) -> Result<Self> {
+        let key_path = config
+            .client_assertion_key
+            .as_deref()
+            .context("client_assertion_key is required for private_key_jwt")?;
+        let mut blocks = read_pem(key_path)?;
+        if let Some(cert_path) = &config.client_assertion_certificate {
+            blocks.extend(read_pem(cert_path)?);
+        }
+
+        let alg = config.client_assertion_alg;
+        let private_key = blocks
+            .iter()
+            .find(|b| b.tag().ends_with("PRIVATE KEY"))
+            .with_context(|| format!("No private key in {}", key_path.display()))?;
+        let private_key = pem::encode(private_key);
+        let key = match alg {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => EncodingKey::from_rsa_pem(private_key.as_bytes()),
+            Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(private_key.as_bytes()),
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(private_key.as_bytes()),
+            alg => bail!("{:?} cannot be used for client assertions", alg),
+        }
+        .with_context(|| format!("Failed to load {:?} key from {}", alg, key_path.display()))?;
+
+        let mut header = Header::new(alg);
+        header.kid = config.client_assertion_kid.clone();
+        if let Some(cert) = blocks.iter().find(|b| b.tag() == "CERTIFICATE") {
+            header.x5t = Some(thumbprint(&SHA1_FOR_LEGACY_USE_ONLY, cert.contents()));
+            header.x5t_s256 = Some(thumbprint(&SHA256, cert.contents()));
+        }
+
+        Ok(Self {
+            client_id: config.client_id.clone(),
+            key,
+            header,
+        })
+    }
+
+    /// Signs a single-use assertion for `audience`, the token endpoint.
+    pub fn sign(&self, audience: &Url) -> Result<String> {
+        let mut jti = [0u8; 16];
+        SystemRandom::new()
+            .fill(&mut jti)
+            .map_err(|_| anyhow::anyhow!("Failed to generate assertion id"))?;
+
+        let now = Utc::now().timestamp();
+        let claims = AssertionClaims {
+            iss: &self.client_id,
+            sub: &self.client_id,
+            aud: audience.as_str(),
+            jti: URL_SAFE_NO_PAD.encode(jti),
+            iat: now,
+            exp: now + ASSERTION_LIFETIME,
+        };
+        encode(&self.header, &claims, &self.key).context("Failed to sign client assertion")
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<pem::Pem>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    pem::parse_many(data).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+pub(crate) fn thumbprint(algorithm: &'static ring::digest::Algorithm, der: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(digest(algorithm, der))
+}
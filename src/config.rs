@@ -1,40 +1,223 @@
+use jsonwebtoken::Algorithm;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{Error as IOError, Read};
+use std::path::PathBuf;
 use std::result::Result;
 use std::time::Duration;
 use url::Url;
 
+use crate::authorization::AuthorizationRules;
+use crate::claims::ClaimRequirements;
+use crate::session_env::SessionEnv;
+use crate::client_auth::ClientAuthMethod;
+use crate::error::ErrorClass;
+use crate::provider::Provider;
+use crate::username::UsernameTransform;
 
 #[serde_with::serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub client_id: String,
-    pub client_secret: String,
-    pub oauth_auth_url: Url,
-    pub oauth_device_url: Url,
-    pub oauth_token_url: Url,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+
+    #[serde(default)]
+    pub token_endpoint_auth_method: Option<ClientAuthMethod>,
+
+    #[serde(default)]
+    pub client_assertion_key: Option<PathBuf>,
+
+    #[serde(default)]
+    pub client_assertion_certificate: Option<PathBuf>,
+
+    #[serde(default = "default_client_assertion_alg")]
+    pub client_assertion_alg: Algorithm,
+
+    #[serde(default)]
+    pub client_assertion_kid: Option<String>,
+
+    #[serde(default)]
+    pub client_certificate: Option<PathBuf>,
+
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+
+    #[serde(default)]
+    pub require_certificate_bound_tokens: bool,
+
+    #[serde(default)]
+    pub https_proxy: Option<Url>,
+
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+
+    #[serde(default = "default_http_connect_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub http_connect_timeout: Duration,
+
+    #[serde(default = "default_http_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub http_timeout: Duration,
+
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+
+    #[serde(default)]
+    pub oauth_auth_url: Option<Url>,
+    #[serde(default)]
+    pub oauth_device_url: Option<Url>,
+    #[serde(default)]
+    pub oauth_token_url: Option<Url>,
     #[serde(default)]
     pub oauth_token_introspect_url: Option<Url>,
+    #[serde(default)]
+    pub userinfo_url: Option<Url>,
+    #[serde(default)]
+    pub revocation_url: Option<Url>,
     pub tenant_id: Option<String>,
+
+    #[serde(default)]
+    pub provider: Option<Provider>,
+
+    #[serde(default)]
+    pub jwks_url: Option<Url>,
+
+    #[serde(default)]
+    #[serde_as(as = "serde_with::OneOrMany<_>")]
+    pub issuer: Vec<String>,
+
     #[serde(default)]
     #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
     pub oauth_device_token_polling_timeout: Option<Duration>,
 
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+
+    #[serde(default = "default_discovery_cache_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub discovery_cache_ttl: Duration,
+
+    #[serde(default = "default_jwks_cache_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub jwks_cache_ttl: Duration,
+
+    #[serde(default = "default_jwks_refetch_interval")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub jwks_refetch_interval: Duration,
+
+    #[serde(default)]
+    pub token_validation: TokenValidation,
+
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::OneOrMany<_>>")]
+    pub username_claim: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub username_transforms: Vec<UsernameTransform>,
+
+    #[serde(default)]
+    pub claim_requirements: ClaimRequirements,
+
+    /// Claim, or JSON pointer, holding the time in seconds since the epoch at
+    /// which the account expires at the provider.
+    #[serde(default)]
+    pub account_expiry_claim: Option<String>,
+
+    #[serde(default = "default_allowed_algorithms")]
+    pub allowed_algorithms: Vec<Algorithm>,
+
     #[serde(default = "default_scopes")]
     pub scopes: String,
 
     #[serde(default = "default_true")]
     pub qr_enabled: bool,
 
+    #[serde(default)]
+    pub auto_poll: bool,
+
+    #[serde(default = "default_status_interval")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub status_interval: Duration,
+
+    #[serde(default)]
+    pub device_code_renewals: u32,
+
     #[serde(default)]
     pub messages: Messages,
 
     #[serde(default)]
     pub allowed_groups: Option<Vec<String>>,
 
+    #[serde(default)]
+    pub authorization: AuthorizationRules,
+
+    #[serde(default = "default_graph_url")]
+    pub graph_url: Url,
+
     #[serde(default)]
     pub local_group: Option<String>,
+
+    #[serde(default)]
+    pub account_map: Option<PathBuf>,
+
+    #[serde(default)]
+    pub access_rules: Option<PathBuf>,
+
+    #[serde(default)]
+    pub session_env: SessionEnv,
+
+    /// Path of the file `sm_setcred` writes the tokens to, with `{uid}` and
+    /// `{user}` replaced.
+    #[serde(default)]
+    pub token_cache: Option<String>,
+
+    /// Revoke the session's tokens at `revocation_url` when it closes.
+    #[serde(default)]
+    pub revoke_on_close: bool,
+
+    /// Timeout of each revocation request made by `revoke_on_close`, which
+    /// replaces `http_timeout` so a slow provider cannot hold up the logout.
+    #[serde(default = "default_revocation_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub revocation_timeout: Duration,
+
+    /// How long a device-flow login satisfies later authentications of the
+    /// same user, service, TTY and login session. Zero disables it.
+    #[serde(default)]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub grace_period: Duration,
+
+    #[serde(default = "default_grace_dir")]
+    pub grace_dir: PathBuf,
+
+    /// Services whose authentications first try a stored refresh token.
+    /// Empty disables storing refresh tokens.
+    #[serde(default)]
+    pub refresh_token_services: Vec<String>,
+
+    /// How long a stored refresh token is used before the device flow is
+    /// required again.
+    #[serde(default = "default_refresh_token_max_age")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub refresh_token_max_age: Duration,
+
+    #[serde(default)]
+    pub fail_open: Vec<ErrorClass>,
+}
+
+/// How the token returned by the device flow is checked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenValidation {
+    /// Verify the signature and claims of the `id_token` against the provider's JWKS.
+    #[default]
+    Jwt,
+    /// Send the `access_token` to `oauth_token_introspect_url` (RFC 7662).
+    Introspection,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,6 +234,26 @@ pub struct Messages {
     pub prompt_code: String,
     #[serde(default = "Messages::default_enter")]
     pub prompt_enter: String,
+    #[serde(default = "Messages::default_auto_poll")]
+    pub prompt_auto_poll: String,
+    #[serde(default = "Messages::default_poll_access_denied")]
+    pub poll_access_denied: String,
+    #[serde(default = "Messages::default_poll_expired")]
+    pub poll_expired: String,
+    #[serde(default = "Messages::default_poll_timeout")]
+    pub poll_timeout: String,
+    #[serde(default = "Messages::default_poll_failed")]
+    pub poll_failed: String,
+    #[serde(default = "Messages::default_poll_cancelled")]
+    pub poll_cancelled: String,
+    #[serde(default = "Messages::default_status_waiting")]
+    pub status_waiting: String,
+    #[serde(default = "Messages::default_status_renewing")]
+    pub status_renewing: String,
+    #[serde(default = "Messages::default_account_expired")]
+    pub account_expired: String,
+    #[serde(default = "Messages::default_password_expired")]
+    pub password_expired: String,
 }
 
 impl Messages {
@@ -72,6 +275,43 @@ impl Messages {
     fn default_enter() -> String {
         "Press \"ENTER\" after successful authentication...".to_string()
     }
+    fn default_auto_poll() -> String {
+        "Press \"ENTER\" after successful authentication, or type \"q\" to cancel...".to_string()
+    }
+    fn default_poll_access_denied() -> String {
+        "The sign-in request was denied.".to_string()
+    }
+    fn default_poll_expired() -> String {
+        "The code has expired. Please log in again to get a new one.".to_string()
+    }
+    fn default_poll_timeout() -> String {
+        "Timed out waiting for the sign-in to complete.".to_string()
+    }
+    fn default_poll_failed() -> String {
+        "The sign-in could not be completed. Please contact your administrator.".to_string()
+    }
+    fn default_poll_cancelled() -> String {
+        "Sign-in cancelled.".to_string()
+    }
+    fn default_status_waiting() -> String {
+        "Still waiting for sign-in, {remaining} left...".to_string()
+    }
+    fn default_status_renewing() -> String {
+        "The code has expired, requesting a new one...".to_string()
+    }
+    fn default_account_expired() -> String {
+        "Your account has expired. Please contact your administrator.".to_string()
+    }
+    fn default_password_expired() -> String {
+        "Your password has expired. Change it with your identity provider and log in again.".to_string()
+    }
+
+    /// `status_waiting` with `{remaining}` replaced by the time left as `m:ss`.
+    pub fn waiting(&self, remaining: Duration) -> String {
+        let secs = remaining.as_secs();
+        self.status_waiting
+            .replace("{remaining}", &format!("{}:{:02}", secs / 60, secs % 60))
+    }
 }
 
 impl Default for Messages {
@@ -83,6 +323,59 @@ impl Default for Messages {
             prompt_no_qr_incomplete: Messages::default_no_qr_incomplete(),
             prompt_code: Messages::default_code(),
             prompt_enter: Messages::default_enter(),
+            prompt_auto_poll: Messages::default_auto_poll(),
+            poll_access_denied: Messages::default_poll_access_denied(),
+            poll_expired: Messages::default_poll_expired(),
+            poll_timeout: Messages::default_poll_timeout(),
+            poll_failed: Messages::default_poll_failed(),
+            poll_cancelled: Messages::default_poll_cancelled(),
+            status_waiting: Messages::default_status_waiting(),
+            status_renewing: Messages::default_status_renewing(),
+            account_expired: Messages::default_account_expired(),
+            password_expired: Messages::default_password_expired(),
+        }
+    }
+}
+
+impl Config {
+    pub fn provider(&self) -> Provider {
+        self.provider.unwrap_or_else(|| Provider::detect(self))
+    }
+
+    /// `client_secret_post` when a secret is configured, `tls_client_auth` when
+    /// only a client certificate is, a public client otherwise.
+    pub fn client_auth_method(&self) -> ClientAuthMethod {
+        match (self.token_endpoint_auth_method, &self.client_secret, &self.client_certificate) {
+            (Some(method), _, _) => method,
+            (None, Some(_), _) => ClientAuthMethod::ClientSecretPost,
+            (None, None, Some(_)) => ClientAuthMethod::TlsClientAuth,
+            (None, None, None) => ClientAuthMethod::None,
+        }
+    }
+
+    /// Claims tried in order to find the remote username in a token checked by `source`.
+    pub fn username_claims(&self, source: TokenValidation) -> Vec<String> {
+        if let Some(claims) = &self.username_claim {
+            return claims.clone();
+        }
+        let defaults: &[&str] = match source {
+            TokenValidation::Jwt => &["preferred_username", "email"],
+            TokenValidation::Introspection => &["username"],
+        };
+        defaults.iter().map(|c| c.to_string()).collect()
+    }
+
+    pub fn jwks_url(&self) -> Option<Url> {
+        self.jwks_url
+            .clone()
+            .or_else(|| self.provider().default_jwks_url(self))
+    }
+
+    pub fn issuers(&self) -> Vec<String> {
+        if self.issuer.is_empty() {
+            self.provider().default_issuers(self)
+        } else {
+            self.issuer.clone()
         }
     }
 }
@@ -100,6 +393,73 @@ fn default_scopes() -> String {
     "openid profile".to_string()
 }
 
+// Only asymmetric algorithms: the module has no shared key to check HMAC signatures with.
+fn default_allowed_algorithms() -> Vec<Algorithm> {
+    vec![
+        Algorithm::RS256,
+        Algorithm::RS384,
+        Algorithm::RS512,
+        Algorithm::PS256,
+        Algorithm::PS384,
+        Algorithm::PS512,
+        Algorithm::ES256,
+        Algorithm::ES384,
+        Algorithm::EdDSA,
+    ]
+}
+
+fn default_client_assertion_alg() -> Algorithm {
+    Algorithm::RS256
+}
+
+fn default_http_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_http_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_revocation_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_refresh_token_max_age() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+fn default_user_agent() -> String {
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string()
+}
+
+fn default_status_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_graph_url() -> Url {
+    Url::parse("https://graph.microsoft.com").expect("valid default Graph URL")
+}
+
 fn default_true() -> bool {
     true
 }
+
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from("/var/lib/pam_oauth2_device")
+}
+
+fn default_grace_dir() -> PathBuf {
+    PathBuf::from("/run/pam_oauth2_device/grace")
+}
+
+fn default_discovery_cache_ttl() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_jwks_cache_ttl() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_jwks_refetch_interval() -> Duration {
+    Duration::from_secs(60)
+}
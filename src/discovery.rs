@@ -0,0 +1,145 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::cache::{CacheEntry, FileCache};
+use crate::config::{Config, TokenValidation};
+use crate::http::HttpClient;
+
+const WELL_KNOWN_PATH: &str = ".well-known/openid-configuration";
+
+/// The subset of the OpenID Provider Metadata the module cares about.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    #[serde(default)]
+    pub authorization_endpoint: Option<Url>,
+    #[serde(default)]
+    pub device_authorization_endpoint: Option<Url>,
+    #[serde(default)]
+    pub token_endpoint: Option<Url>,
+    #[serde(default)]
+    pub jwks_uri: Option<Url>,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<Url>,
+    #[serde(default)]
+    pub revocation_endpoint: Option<Url>,
+    #[serde(default)]
+    pub introspection_endpoint: Option<Url>,
+}
+
+/// Fills every endpoint missing from `config` from the issuer's discovery document.
+/// Discovery only runs when an issuer is configured and an endpoint that is
+/// required, or needed by an enabled feature, is missing.
+pub fn resolve(config: &mut Config, http: &HttpClient) -> Result<()> {
+    let issuer = match config.issuer.first() {
+        Some(issuer) if needs_discovery(config) => issuer.clone(),
+        _ => return Ok(()),
+    };
+
+    let metadata = fetch_metadata(config, http, &issuer)?;
+    log::debug!("Discovered provider metadata: {:#?}", metadata);
+    fill_all(config, metadata);
+    Ok(())
+}
+
+/// Like `resolve`, but only a cached discovery document is used, however old,
+/// and nothing is fetched. Endpoints stay missing if there is none.
+pub fn resolve_cached(config: &mut Config) {
+    let issuer = match config.issuer.first() {
+        Some(issuer) if needs_discovery(config) => issuer.clone(),
+        _ => return,
+    };
+    let Ok(url) = discovery_url(&issuer) else { return };
+    let cache = FileCache::new(&config.cache_dir);
+    if let Some(entry) = cache.read::<ProviderMetadata>(&cache_key(&url)) {
+        fill_all(config, entry.value);
+    }
+}
+
+fn needs_discovery(config: &Config) -> bool {
+    config.oauth_auth_url.is_none()
+        || config.oauth_device_url.is_none()
+        || config.oauth_token_url.is_none()
+        || config.jwks_url().is_none()
+        || (config.revoke_on_close && config.revocation_url.is_none())
+        || (config.token_validation == TokenValidation::Introspection
+            && config.oauth_token_introspect_url.is_none())
+}
+
+fn fill_all(config: &mut Config, metadata: ProviderMetadata) {
+    fill(&mut config.oauth_auth_url, metadata.authorization_endpoint);
+    fill(&mut config.oauth_device_url, metadata.device_authorization_endpoint);
+    fill(&mut config.oauth_token_url, metadata.token_endpoint);
+    fill(&mut config.jwks_url, metadata.jwks_uri);
+    fill(&mut config.userinfo_url, metadata.userinfo_endpoint);
+    fill(&mut config.revocation_url, metadata.revocation_endpoint);
+    fill(&mut config.oauth_token_introspect_url, metadata.introspection_endpoint);
+}
+
+fn fill(field: &mut Option<Url>, discovered: Option<Url>) {
+    if field.is_none() {
+        *field = discovered;
+    }
+}
+
+fn fetch_metadata(config: &Config, http: &HttpClient, issuer: &str) -> Result<ProviderMetadata> {
+    let url = discovery_url(issuer)?;
+    let cache = FileCache::new(&config.cache_dir);
+    let key = cache_key(&url);
+
+    let cached = cache.read::<ProviderMetadata>(&key);
+    if let Some(entry) = cached.as_ref().filter(|e| e.is_fresh()) {
+        log::debug!("Using cached discovery document for {}", issuer);
+        return Ok(entry.value.clone());
+    }
+
+    let metadata = match download(http, &url, issuer) {
+        Ok(metadata) => metadata,
+        // A stale document is better than refusing every login while the IdP is flaky
+        Err(e) => match cached {
+            Some(entry) => {
+                log::warn!("Failed to refresh discovery document, using cached copy: {:#}", e);
+                return Ok(entry.value);
+            }
+            None => return Err(e),
+        },
+    };
+
+    let entry = CacheEntry::new(metadata, config.discovery_cache_ttl);
+    if let Err(e) = cache.write(&key, &entry) {
+        log::warn!("Failed to cache discovery document: {:#}", e);
+    }
+    Ok(entry.value)
+}
+
+fn cache_key(url: &Url) -> String {
+    format!("discovery-{}", url)
+}
+
+fn download(http: &HttpClient, url: &Url, issuer: &str) -> Result<ProviderMetadata> {
+    let metadata: ProviderMetadata = http
+        .get(url.clone())
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .with_context(|| format!("Failed to fetch discovery document from {}", url))?;
+
+    // OpenID Connect Discovery 1.0, section 4.3
+    if metadata.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+        bail!(
+            "Discovered issuer {} does not match configured issuer {}",
+            metadata.issuer,
+            issuer
+        );
+    }
+    Ok(metadata)
+}
+
+fn discovery_url(issuer: &str) -> Result<Url> {
+    let base = format!("{}/", issuer.trim_end_matches('/'));
+    let url = Url::parse(&base)
+        .and_then(|u| u.join(WELL_KNOWN_PATH))
+        .with_context(|| format!("Invalid issuer URL: {}", issuer))?;
+    Ok(url)
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::fmt;
+
+use pam::constants::PamResultCode;
+use serde::{Deserialize, Serialize};
+
+use crate::logger::{DefaultLogger, Logger};
+use crate::poller::PollError;
+
+/// Kinds of authentication failures, each reported to the PAM stack with its
+/// own return code.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// The identity provider could not be reached or answered with a server error.
+    Unavailable,
+    /// Invalid configuration or a local failure such as creating the user.
+    Config,
+    /// The user or the identity provider refused the login, or the token is invalid.
+    Denied,
+    /// The device code expired or polling timed out before the user signed in.
+    Timeout,
+    /// The verified identity may not log in as the requested account.
+    Policy,
+}
+
+impl ErrorClass {
+    pub fn pam_code(self) -> PamResultCode {
+        match self {
+            ErrorClass::Unavailable => PamResultCode::PAM_AUTHINFO_UNAVAIL,
+            ErrorClass::Config => PamResultCode::PAM_SYSTEM_ERR,
+            ErrorClass::Denied => PamResultCode::PAM_AUTH_ERR,
+            ErrorClass::Timeout => PamResultCode::PAM_MAXTRIES,
+            ErrorClass::Policy => PamResultCode::PAM_PERM_DENIED,
+        }
+    }
+
+    /// `PAM_IGNORE` when the class is listed in `fail_open`, so the rest of
+    /// the stack decides, its own code otherwise.
+    pub fn resolve(self, fail_open: &[ErrorClass]) -> PamResultCode {
+        if fail_open.contains(&self) {
+            log::warn!("Ignoring {} error as configured in fail_open", self);
+            return PamResultCode::PAM_IGNORE;
+        }
+        self.pam_code()
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorClass::Unavailable => "unavailable",
+            ErrorClass::Config => "config",
+            ErrorClass::Denied => "denied",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Policy => "policy",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Classified failure of an authentication attempt.
+#[derive(Debug)]
+pub struct AuthError {
+    class: ErrorClass,
+    message: &'static str,
+    source: Box<dyn Error>,
+}
+
+impl AuthError {
+    pub fn new<E: Into<Box<dyn Error>>>(class: ErrorClass, message: &'static str, err: E) -> Self {
+        Self {
+            class,
+            message,
+            source: err.into(),
+        }
+    }
+
+    /// Failure of a request to the identity provider. Connection failures,
+    /// timeouts and 5xx responses are `Unavailable`, anything else, a
+    /// response that cannot be decoded among them, is `class`.
+    pub fn request<E: Into<Box<dyn Error>>>(class: ErrorClass, message: &'static str, err: E) -> Self {
+        let source = err.into();
+        let class = if is_unavailable(source.as_ref()) {
+            ErrorClass::Unavailable
+        } else {
+            class
+        };
+        Self {
+            class,
+            message,
+            source,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        self.class
+    }
+
+    /// Logs the error and returns the PAM code for it.
+    pub fn handle(self, fail_open: &[ErrorClass]) -> PamResultCode {
+        let class = self.class;
+        DefaultLogger::handle_error(self.into(), "Authentication failed");
+        class.resolve(fail_open)
+    }
+}
+
+impl From<PollError> for AuthError {
+    fn from(err: PollError) -> Self {
+        let class = match &err {
+            PollError::AccessDenied(_) | PollError::Server(_) | PollError::Cancelled => {
+                ErrorClass::Denied
+            }
+            PollError::ExpiredToken | PollError::Timeout => ErrorClass::Timeout,
+            PollError::Request(e) if is_unavailable(e.as_ref()) => ErrorClass::Unavailable,
+            PollError::Request(_) => ErrorClass::Denied,
+        };
+        Self::new(class, "Failed to receive user token", err)
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.class)
+    }
+}
+
+impl Error for AuthError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+// A response with a 4xx status or a malformed body came from a reachable
+// provider, so only these must not be let through by `fail_open`
+fn is_unavailable(err: &(dyn Error + 'static)) -> bool {
+    let mut cur = Some(err);
+    while let Some(e) = cur {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return e.is_connect()
+                || e.is_timeout()
+                || e.status().is_some_and(|s| s.is_server_error());
+        }
+        cur = e.source();
+    }
+    false
+}
@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::claims::VerifiedClaims;
+use crate::private_dir::PrivateDir;
+
+const SECRET_FILE: &str = ".secret";
+
+/// What a cached authentication is bound to, like a `sudo` timestamp
+/// record. The login session is identified by its ID together with the
+/// start time of its leader, so an entry is never valid in a later session
+/// that reuses the ID.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GraceKey {
+    pub user: String,
+    pub service: String,
+    pub tty: Option<String>,
+    pub session_id: i32,
+    pub session_start: u64,
+}
+
+impl GraceKey {
+    /// Key for the login session of the calling process.
+    pub fn current(user: &str, service: &str, tty: Option<&str>) -> Option<Self> {
+        let session_id = nix::unistd::getsid(None).ok()?.as_raw();
+        Some(Self {
+            user: user.to_string(),
+            service: service.to_string(),
+            tty: tty.map(str::to_string),
+            session_id,
+            session_start: process_start(session_id)?,
+        })
+    }
+
+    /// Whether the login session still exists, i.e. its leader is running.
+    pub fn session_exists(&self) -> bool {
+        process_start(self.session_id) == Some(self.session_start)
+    }
+
+    pub(crate) fn file_name(&self) -> String {
+        let key = serde_json::to_vec(self).unwrap_or_default();
+        let hash = digest::digest(&digest::SHA256, &key);
+        let hex: String = hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}.json", hex)
+    }
+}
+
+// Field 22 of /proc/<pid>/stat, counted after the parenthesized command name
+fn process_start(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GraceEntry {
+    key: GraceKey,
+    created_at: i64,
+    expires_at: i64,
+    username: String,
+    groups: Option<Vec<String>>,
+    claims: Map<String, Value>,
+}
+
+/// The file as stored: `entry` is kept as the exact bytes the MAC covers.
+#[derive(Serialize, Deserialize, Debug)]
+struct SignedEntry {
+    entry: String,
+    mac: String,
+}
+
+/// Recent authentications that let later ones within `grace_period` skip
+/// the device flow. Entries are kept in a private directory and carry an
+/// HMAC under a secret kept next to them.
+#[derive(Debug)]
+pub struct GraceCache {
+    dir: PrivateDir,
+}
+
+impl GraceCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: PrivateDir::new(dir),
+        }
+    }
+
+    /// Records an authentication for at most `period`, and never beyond the
+    /// token's `exp`. Expired entries of other logins are removed on the way.
+    /// `now` is in seconds since the epoch.
+    pub fn record(
+        &self,
+        key: &GraceKey,
+        claims: &VerifiedClaims,
+        period: Duration,
+        now: i64,
+    ) -> Result<()> {
+        let exp = claims.exp().context("Token has no 'exp' to bound the grace period")?;
+        let entry = GraceEntry {
+            key: key.clone(),
+            created_at: now,
+            expires_at: exp.min(now + period.as_secs() as i64),
+            username: claims.username().to_string(),
+            groups: claims.groups().map(<[String]>::to_vec),
+            claims: claims.claims().clone(),
+        };
+        let entry = serde_json::to_string(&entry)?;
+        let mac = hmac::sign(&self.secret()?, entry.as_bytes());
+        let signed = SignedEntry {
+            entry,
+            mac: STANDARD.encode(mac.as_ref()),
+        };
+        self.dir.write(&key.file_name(), &serde_json::to_vec(&signed)?)?;
+        self.prune(now);
+        Ok(())
+    }
+
+    /// Called when a PAM session of `key` closes. Services such as `sudo`
+    /// open one per command, so the entry stays valid for the rest of the
+    /// login session and is only removed once that has ended.
+    pub fn close(&self, key: &GraceKey) -> Result<()> {
+        if key.session_exists() {
+            return Ok(());
+        }
+        self.dir.remove(&key.file_name())
+    }
+
+    /// Removes every entry that is expired or cannot be verified.
+    fn prune(&self, now: i64) {
+        let names = match self.dir.names() {
+            Ok(names) => names,
+            Err(e) => {
+                log::warn!("Failed to prune grace entries: {:#}", e);
+                return;
+            }
+        };
+        for name in names {
+            let valid = matches!(
+                self.load(&name),
+                Ok(Some(entry)) if entry.created_at <= now && now < entry.expires_at
+            );
+            if !valid {
+                log::debug!("Removing grace entry {}", self.dir.path(&name).display());
+                let _ = self.dir.remove(&name);
+            }
+        }
+    }
+
+    /// Claims of a valid entry for `key`. Expired or tampered entries are
+    /// removed.
+    pub fn lookup(&self, key: &GraceKey, now: i64) -> Option<VerifiedClaims> {
+        match self.read(key, now) {
+            Ok(Some(entry)) => {
+                log::info!(
+                    "Reusing authentication of {} for {} ({}s left)",
+                    entry.username,
+                    key.user,
+                    entry.expires_at - now
+                );
+                Some(VerifiedClaims::restore(entry.username, entry.groups, entry.claims))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                let name = key.file_name();
+                log::warn!("Ignoring grace entry {}: {:#}", self.dir.path(&name).display(), e);
+                let _ = self.dir.remove(&name);
+                None
+            }
+        }
+    }
+
+    fn read(&self, key: &GraceKey, now: i64) -> Result<Option<GraceEntry>> {
+        let Some(entry) = self.load(&key.file_name())? else {
+            return Ok(None);
+        };
+        if entry.key != *key {
+            bail!("Entry is for a different login");
+        }
+        // A clock set back could otherwise stretch the window
+        if now < entry.created_at {
+            bail!("Entry was created in the future");
+        }
+        if now >= entry.expires_at {
+            bail!("Expired at {}", entry.expires_at);
+        }
+        Ok(Some(entry))
+    }
+
+    /// The entry stored as `name`, once its MAC checks out.
+    fn load(&self, name: &str) -> Result<Option<GraceEntry>> {
+        let Some(contents) = self.dir.read(name)? else {
+            return Ok(None);
+        };
+        let signed: SignedEntry = serde_json::from_slice(&contents)?;
+        let mac = STANDARD.decode(&signed.mac)?;
+        hmac::verify(&self.secret()?, signed.entry.as_bytes(), &mac)
+            .map_err(|_| anyhow::anyhow!("MAC mismatch"))?;
+        Ok(Some(serde_json::from_str(&signed.entry)?))
+    }
+
+    fn secret(&self) -> Result<hmac::Key> {
+        let secret: [u8; 32] = self.dir.secret(SECRET_FILE)?;
+        Ok(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+    }
+}
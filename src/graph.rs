@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::claims::VerifiedClaims;
+use crate::http::HttpClient;
+
+/// Whether Azure left out the `groups` claim because the user is in too many
+/// groups. It then sends `_claim_names`/`_claim_sources` pointing at the
+/// groups (JWTs up to 200 groups) or `hasgroups` (implicit flow).
+pub fn has_group_overage(claims: &VerifiedClaims) -> bool {
+    let claim_names = claims
+        .get("_claim_names")
+        .and_then(|names| names.get("groups"))
+        .is_some();
+    let has_groups = claims.get("hasgroups").and_then(Value::as_bool) == Some(true);
+    claims.groups().is_none() && (claim_names || has_groups)
+}
+
+/// Scope of an app-only token for the Graph API at `graph_url`.
+pub fn scope(graph_url: &Url) -> String {
+    format!("{}/.default", graph_url.as_str().trim_end_matches('/'))
+}
+
+#[derive(Deserialize)]
+struct MemberObjects {
+    value: Vec<String>,
+}
+
+/// IDs of all groups the user with object ID `user_id` belongs to, directly
+/// or through nested groups, from `getMemberObjects`.
+pub fn member_objects(
+    http: &HttpClient,
+    graph_url: &Url,
+    access_token: &str,
+    user_id: &str,
+) -> Result<Vec<String>> {
+    let mut url = graph_url.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid graph_url {}", graph_url))?
+        .pop_if_empty()
+        .extend(&["v1.0", "users", user_id, "getMemberObjects"]);
+
+    let resp: MemberObjects = http
+        .post(url.clone())
+        .bearer_auth(access_token)
+        .json(&json!({ "securityEnabledOnly": false }))
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to query {}", url))?
+        .json()
+        .context("Malformed getMemberObjects response")?;
+    Ok(resp.value)
+}
@@ -0,0 +1,123 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::{Certificate, Identity, IntoUrl, NoProxy, Proxy};
+
+use crate::client_auth::thumbprint;
+use crate::config::Config;
+
+/// Blocking HTTP client used for every request to the identity provider, so
+/// proxy, trust roots, timeouts and the mTLS identity apply to all of them.
+/// Clones share one connection pool.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    certificate_thumbprint: Option<String>,
+}
+
+impl HttpClient {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut builder = Client::builder()
+            .connect_timeout(config.http_connect_timeout)
+            .timeout(config.http_timeout)
+            .user_agent(config.user_agent.as_str());
+        let mut certificate_thumbprint = None;
+
+        // Without `https_proxy` the usual proxy environment variables apply
+        if let Some(proxy_url) = &config.https_proxy {
+            let proxy = Proxy::all(proxy_url.as_str())
+                .with_context(|| format!("Invalid https_proxy {}", proxy_url))?
+                .no_proxy(config.no_proxy.as_deref().and_then(NoProxy::from_string));
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(ca_path) = &config.ca_bundle {
+            let bundle = fs::read(ca_path)
+                .with_context(|| format!("Failed to read {}", ca_path.display()))?;
+            let certs = Certificate::from_pem_bundle(&bundle)
+                .with_context(|| format!("Failed to parse {}", ca_path.display()))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let Some(cert_path) = &config.client_certificate {
+            let mut pem = fs::read(cert_path)
+                .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+            let cert = pem::parse_many(&pem)
+                .with_context(|| format!("Failed to parse {}", cert_path.display()))?
+                .into_iter()
+                .find(|b| b.tag() == "CERTIFICATE")
+                .with_context(|| format!("No certificate in {}", cert_path.display()))?;
+            certificate_thumbprint = Some(thumbprint(&ring::digest::SHA256, cert.contents()));
+
+            // The key may live next to the certificate
+            if let Some(key_path) = &config.client_key {
+                pem.push(b'\n');
+                pem.extend(
+                    fs::read(key_path)
+                        .with_context(|| format!("Failed to read {}", key_path.display()))?,
+                );
+            }
+            let identity = Identity::from_pem(&pem).context("Failed to load client certificate")?;
+            builder = builder.use_rustls_tls().identity(identity);
+        }
+
+        Ok(Self {
+            client: builder.build().context("Failed to build HTTP client")?,
+            certificate_thumbprint,
+        })
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Base64url SHA-256 thumbprint of the client certificate, the value
+    /// certificate-bound tokens carry in `cnf.x5t#S256` (RFC 8705).
+    pub fn certificate_thumbprint(&self) -> Option<&str> {
+        self.certificate_thumbprint.as_deref()
+    }
+
+    /// Sends a request built by the `oauth2` crate.
+    pub fn oauth2(
+        &self,
+        request: oauth2::HttpRequest,
+    ) -> Result<oauth2::HttpResponse, reqwest::Error> {
+        let method = reqwest::Method::from_bytes(request.method.as_str().as_bytes())
+            .unwrap_or(reqwest::Method::POST);
+        let mut builder = self.client.request(method, request.url.as_str());
+        for (name, value) in &request.headers {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        let mut resp = builder.body(request.body).send()?;
+        // OAuth error responses are 4xx, a 5xx means the provider itself failed
+        if resp.status().is_server_error() {
+            resp = resp.error_for_status()?;
+        }
+
+        let status_code = oauth2::http::StatusCode::from_u16(resp.status().as_u16())
+            .unwrap_or(oauth2::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut headers = oauth2::http::HeaderMap::new();
+        for (name, value) in resp.headers() {
+            if let (Ok(name), Ok(value)) = (
+                oauth2::http::HeaderName::from_bytes(name.as_str().as_bytes()),
+                oauth2::http::HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        let body = resp.bytes()?.to_vec();
+
+        Ok(oauth2::HttpResponse {
+            status_code,
+            headers,
+            body,
+        })
+    }
+}
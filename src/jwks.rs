@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, CACHE_CONTROL};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::{Deserialize, Deserializer, Serialize};
+use url::Url;
+
+use crate::cache::{now, CacheEntry, FileCache};
+use crate::config::Config;
+use crate::http::HttpClient;
+
+/// Public key material of a JWK, tagged by `kty` (RFC 7518, section 6).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kty")]
+pub enum KeyParams {
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    #[serde(rename = "EC")]
+    Ec { crv: String, x: String, y: String },
+    #[serde(rename = "OKP")]
+    Okp { crv: String, x: String },
+}
+
+impl KeyParams {
+    fn describe(&self) -> String {
+        match self {
+            KeyParams::Rsa { .. } => "RSA".to_string(),
+            KeyParams::Ec { crv, .. } => format!("EC {}", crv),
+            KeyParams::Okp { crv, .. } => format!("OKP {}", crv),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Jwk {
+    #[serde(flatten)]
+    pub params: KeyParams,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub alg: Option<String>,
+    #[serde(default, rename = "use")]
+    pub use_: Option<String>,
+}
+
+impl Jwk {
+    /// Builds the verification key for `alg`, refusing key types that cannot produce it.
+    pub fn decoding_key(&self, alg: Algorithm) -> Result<DecodingKey> {
+        if let Some(jwk_alg) = self.alg.as_deref() {
+            if jwk_alg != format!("{:?}", alg) {
+                bail!("Key is restricted to {}, token uses {:?}", jwk_alg, alg);
+            }
+        }
+        if self.use_.as_deref().is_some_and(|u| u != "sig") {
+            bail!("Key is not a signing key");
+        }
+
+        let key = match (&self.params, alg) {
+            (
+                KeyParams::Rsa { n, e },
+                Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512,
+            ) => DecodingKey::from_rsa_components(n, e)?,
+            (KeyParams::Ec { crv, x, y }, Algorithm::ES256) if crv == "P-256" => {
+                DecodingKey::from_ec_components(x, y)?
+            }
+            (KeyParams::Ec { crv, x, y }, Algorithm::ES384) if crv == "P-384" => {
+                DecodingKey::from_ec_components(x, y)?
+            }
+            (KeyParams::Okp { crv, x }, Algorithm::EdDSA) if crv == "Ed25519" => {
+                DecodingKey::from_ed_components(x)?
+            }
+            (params, alg) => bail!("{} key cannot verify {:?} signatures", params.describe(), alg),
+        };
+        Ok(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Jwks {
+    #[serde(deserialize_with = "supported_keys")]
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    fn find(&self, kid: &str) -> Option<&Jwk> {
+        self.keys.iter().find(|j| j.kid.as_deref() == Some(kid))
+    }
+}
+
+// Last download per cache directory and key set URL in this process, in
+// case the cache cannot be written
+static LAST_FETCH: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Key set cached in `cache_dir` and shared by all processes that load the module.
+///
+/// A fresh cache is only bypassed when the token's `kid` is unknown, and then at
+/// most once per `jwks_refetch_interval`, so forged `kid`s cannot force a download
+/// on every login attempt. The time of the last download is kept apart from the
+/// key set, so the limit also holds when the set itself may not be reused.
+pub struct JwksCache {
+    url: Url,
+    http: HttpClient,
+    cache: FileCache,
+    default_ttl: Duration,
+    refetch_interval: Duration,
+    /// Key of this set in `LAST_FETCH`.
+    fetch_id: String,
+}
+
+impl JwksCache {
+    pub fn new(config: &Config, http: &HttpClient, url: Url) -> Self {
+        Self {
+            url: url.clone(),
+            http: http.clone(),
+            cache: FileCache::new(&config.cache_dir),
+            default_ttl: config.jwks_cache_ttl,
+            refetch_interval: config.jwks_refetch_interval,
+            fetch_id: format!("{}\0{}", config.cache_dir.display(), url),
+        }
+    }
+
+    pub fn find(&self, kid: &str) -> Result<Jwk> {
+        let key = format!("jwks-{}", self.url);
+        let cached = self.cache.read::<Jwks>(&key);
+
+        if let Some(entry) = cached.as_ref().filter(|e| e.is_fresh()) {
+            if let Some(jwk) = entry.value.find(kid) {
+                log::debug!("Using cached JWK for kid {}", kid);
+                return Ok(jwk.clone());
+            }
+        }
+        // Checked even when the key set may not be reused, such as after
+        // `no-store` or a failed cache write
+        let known = cached.as_ref().and_then(|e| e.value.find(kid)).is_some();
+        if !known {
+            if let Some(age) = self.last_fetch_age(cached.as_ref()) {
+                if age < self.refetch_interval {
+                    bail!(
+                        "No matching key for kid {} (key set refreshed {}s ago)",
+                        kid,
+                        age.as_secs()
+                    );
+                }
+            }
+            if cached.is_some() {
+                log::info!("Unknown kid {}, refreshing key set", kid);
+            }
+        }
+
+        let entry = match self.download() {
+            Ok(entry) => {
+                self.record_fetch(entry.fetched_at);
+                if let Err(e) = self.cache.write(&key, &entry) {
+                    log::warn!("Failed to cache key set: {:#}", e);
+                }
+                entry
+            }
+            Err(e) => match cached {
+                Some(stale) if stale.value.find(kid).is_some() => {
+                    log::warn!("Failed to refresh key set, using cached copy: {:#}", e);
+                    stale
+                }
+                _ => return Err(e),
+            },
+        };
+
+        entry
+            .value
+            .find(kid)
+            .cloned()
+            .with_context(|| format!("No matching key for kid {}", kid))
+    }
+
+    /// Time since the key set was last downloaded by any process, as far as
+    /// the cached set, the separate timestamp file or this process know.
+    fn last_fetch_age(&self, cached: Option<&CacheEntry<Jwks>>) -> Option<Duration> {
+        let remembered = LAST_FETCH.lock().ok().and_then(|f| f.get(&self.fetch_id).copied());
+        let stamped = self.cache.read::<()>(&self.fetch_key()).map(|e| e.fetched_at);
+        let fetched_at = [cached.map(|e| e.fetched_at), stamped, remembered]
+            .into_iter()
+            .flatten()
+            .max()?;
+        Some(Duration::from_secs(now().saturating_sub(fetched_at)))
+    }
+
+    fn record_fetch(&self, fetched_at: u64) {
+        if let Ok(mut fetches) = LAST_FETCH.lock() {
+            fetches.insert(self.fetch_id.clone(), fetched_at);
+        }
+        let stamp = CacheEntry {
+            fetched_at,
+            expires_at: fetched_at,
+            value: (),
+        };
+        if let Err(e) = self.cache.write(&self.fetch_key(), &stamp) {
+            log::warn!("Failed to record key set download: {:#}", e);
+        }
+    }
+
+    fn fetch_key(&self) -> String {
+        format!("jwks-fetched-{}", self.url)
+    }
+
+    fn download(&self) -> Result<CacheEntry<Jwks>> {
+        let resp = self
+            .http
+            .get(self.url.clone())
+            .send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to fetch JWKS from {}", self.url))?;
+        let ttl = cache_ttl(resp.headers()).unwrap_or(self.default_ttl);
+        let jwks: Jwks = resp.json().context("Failed to parse JWKS")?;
+        log::debug!("Fetched {} keys from {}, cached for {}s", jwks.keys.len(), self.url, ttl.as_secs());
+        Ok(CacheEntry::new(jwks, ttl))
+    }
+}
+
+// Key sets may also publish symmetric or encryption-only keys. Skip whatever
+// cannot be modelled instead of rejecting the whole set.
+fn supported_keys<'de, D>(deserializer: D) -> std::result::Result<Vec<Jwk>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| match serde_json::from_value::<Jwk>(key) {
+            Ok(jwk) => Some(jwk),
+            Err(e) => {
+                log::debug!("Skipping unsupported JWK: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+// Only `max-age` and the directives forbidding reuse matter for a key set.
+fn cache_ttl(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(CACHE_CONTROL)?.to_str().ok()?;
+    let mut ttl = None;
+    for directive in value.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+        if directive == "no-store" || directive == "no-cache" {
+            return Some(Duration::ZERO);
+        }
+        if let Some(secs) = directive.strip_prefix("max-age=") {
+            ttl = secs.trim_matches('"').parse().ok().map(Duration::from_secs);
+        }
+    }
+    ttl
+}
@@ -1,21 +1,57 @@
+pub mod access_rules;
+pub mod account;
+pub mod account_map;
+pub mod authorization;
+pub mod cache;
+pub mod claims;
+pub mod client_auth;
 pub mod config;
+pub mod discovery;
+pub mod error;
+pub mod grace;
+pub mod graph;
+pub mod http;
+pub mod jwks;
 pub mod logger;
 pub mod oauth_device;
+pub mod poller;
 pub mod prompt;
+pub mod provider;
+pub mod refresh_tokens;
+pub mod session_env;
+pub mod token_cache;
+pub mod username;
 
-use crate::config::read_config;
+use crate::access_rules::{AccessRules, Action, LoginContext};
+use crate::account::{AccountStatus, AuthDecision, DECISION_KEY};
+use crate::claims::VerifiedClaims;
+use crate::config::{read_config, Config};
+use crate::error::{AuthError, ErrorClass};
+use crate::grace::{GraceCache, GraceKey};
 use crate::oauth_device::*;
-use pam::constants::{PamFlag, PamResultCode, PAM_PROMPT_ECHO_OFF};
+use crate::poller::{CancelFlag, PollError, Poller};
+use oauth2::devicecode::StandardDeviceAuthorizationResponse;
+use oauth2::TokenResponse;
+use pam::constants::{
+    PamFlag, PamResultCode, PAM_DELETE_CRED, PAM_ERROR_MSG, PAM_PROMPT_ECHO_OFF, PAM_TEXT_INFO,
+};
 
 use crate::prompt::UserPrompt;
+use crate::refresh_tokens::{RefreshTokenStore, StoredToken};
+use crate::token_cache::{CachedTokens, TokenCache, TOKENS_KEY};
+use anyhow::Context;
+use chrono::Utc;
 use logger::{DefaultLogger, Logger};
 use pam::conv::Conv;
-use pam::module::{PamHandle, PamHooks};
+use pam::items::{RHost, Service, Tty};
+use pam::module::{PamHandle, PamHooks, PamResult};
 use pam::pam_try;
 use std::collections::HashMap;
-use std::ffi::CStr;
-use oauth2::TokenResponse;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::sync::mpsc;
 
+mod private_dir;
 mod user;
 use crate::user::create_local_user;
 
@@ -23,33 +59,17 @@ pub struct PamOAuth2Device;
 pam::pam_hooks!(PamOAuth2Device);
 
 macro_rules! try_or_handle {
-    ($res:expr, $error_message:expr, $pam_error:expr) => {
+    ($res:expr, $error_class:expr, $error_message:expr, $fail_open:expr) => {
         match $res {
             Ok(o) => o,
-            Err(e) => {
-                DefaultLogger::handle_error(e.into(), $error_message); // <-- fix qui
-                return $pam_error;
-            }
+            Err(e) => return AuthError::request($error_class, $error_message, e).handle($fail_open),
         }
     };
 }
 
 impl PamHooks for PamOAuth2Device {
     fn sm_authenticate(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
-        let args = parse_args(&args);
-        let default_log_path = "/tmp/pam_oauth2_device.log".to_string();
-        let default_log_level = "info".to_string();
-        let log_path = args.get("logs").unwrap_or(&default_log_path);
-        let log_level = args.get("log_level").unwrap_or(&default_log_level);
-        DefaultLogger::init(&log_path, &log_level);
-
-        let default_config_path = "/etc/pam_oauth2_device/config.json".to_string();
-        let config_path = args.get("config").unwrap_or(&default_config_path);
-        let config = try_or_handle!(
-            read_config(&config_path).map_err(|err| Box::new(err) as Box<dyn std::error::Error>),
-            "Failed to parse config file",
-            PamResultCode::PAM_SYSTEM_ERR
-        );
+        let config = pam_try!(setup(&args));
 
         let conv = match pamh.get_item::<Conv>() {
             Ok(Some(conv)) => conv,
@@ -68,85 +88,522 @@ impl PamHooks for PamOAuth2Device {
 
         let oauth_client = try_or_handle!(
             OAuthClient::new(&config),
+            ErrorClass::Config,
             "Failed to build OAuth client",
-            PamResultCode::PAM_SYSTEM_ERR
+            &config.fail_open
         );
         log::debug!("OAuth Client: {:#?}", oauth_client);
 
-        let device_code_resp = try_or_handle!(
-            oauth_client.device_code(),
-            "Failed to receive device code response",
-            PamResultCode::PAM_AUTH_ERR
-        );
-        log::debug!("Device Code response: {:#?}", device_code_resp);
+        let grace = GraceCache::new(&config.grace_dir);
+        let grace_key = if config.grace_period.is_zero() {
+            None
+        } else {
+            grace_key(pamh, &local_username)
+        };
+        let reused = grace_key
+            .as_ref()
+            .and_then(|key| grace.lookup(key, Utc::now().timestamp()));
+        let refresh_service = pamh
+            .get_item::<Service>()
+            .ok()
+            .flatten()
+            .map(|service| service.0.to_string_lossy().into_owned())
+            .filter(|service| config.refresh_token_services.contains(service));
 
-        let mut user_prompt = UserPrompt::new(&device_code_resp, &config.messages);
-        if config.qr_enabled {
-            log::debug!("Generating QR code...");
-            user_prompt.generate_qr();
-        }
-        log::debug!("User prompt: {:#?}", user_prompt);
+        let silent = match (reused, &refresh_service) {
+            (Some(claims), _) => Some((claims, None)),
+            (None, Some(service)) => refresh_silently(&oauth_client, &config, &local_username, service)
+                .map(|(claims, token)| (claims, Some(token))),
+            (None, None) => None,
+        };
 
-        pam_try!(conv.send(PAM_PROMPT_ECHO_OFF, &user_prompt.to_string()));
+        let (claims, token) = match silent {
+            Some(silent) => silent,
+            None => {
+                let mut renewals = 0;
+                let token = loop {
+                    let device_code_resp = try_or_handle!(
+                        oauth_client.device_code(),
+                        ErrorClass::Config,
+                        "Failed to receive device code response",
+                        &config.fail_open
+                    );
+                    log::debug!("Device Code response: {:#?}", device_code_resp);
 
-        let token = try_or_handle!(
-            oauth_client.get_token(&device_code_resp, config.oauth_device_token_polling_timeout),
-            "Failed to receive user token",
-            PamResultCode::PAM_AUTH_ERR
-        );
-        log::debug!("Token response: {:#?}", token);
+                    let mut user_prompt = UserPrompt::new(&device_code_resp, &config.messages);
+                    user_prompt.set_auto_poll(config.auto_poll);
+                    if config.qr_enabled {
+                        log::debug!("Generating QR code...");
+                        user_prompt.generate_qr();
+                    }
+                    log::debug!("User prompt: {:#?}", user_prompt);
 
-        let remote_username: String = try_or_handle!(
-            oauth_client.introspect_username(&token),
-            "Failed to introspect user token",
-            PamResultCode::PAM_AUTH_ERR
-        );
+                    match pam_try!(poll_interactively(
+                        &conv,
+                        &oauth_client,
+                        &device_code_resp,
+                        &user_prompt.to_string(),
+                        &config
+                    )) {
+                        Ok(token) => break token,
+                        Err(PollError::ExpiredToken) if renewals < config.device_code_renewals => {
+                            renewals += 1;
+                            log::info!(
+                                "Device code expired, requesting a new one ({}/{})",
+                                renewals,
+                                config.device_code_renewals
+                            );
+                            let _ = conv.send(PAM_TEXT_INFO, &config.messages.status_renewing);
+                        }
+                        Err(e) => {
+                            let _ = conv.send(PAM_ERROR_MSG, e.user_message(&config.messages));
+                            return AuthError::from(e).handle(&config.fail_open);
+                        }
+                    }
+                };
+                log::debug!("Token response: {:#?}", token);
+
+                let claims = try_or_handle!(
+                    oauth_client.verify(&token),
+                    ErrorClass::Denied,
+                    "Failed to verify user token",
+                    &config.fail_open
+                );
+                (claims, Some(token))
+            }
+        };
+        let mut policy = Action::Allow;
+        if let Some(path) = &config.access_rules {
+            let rules = try_or_handle!(
+                AccessRules::load(path),
+                ErrorClass::Config,
+                "Failed to load access rules",
+                &config.fail_open
+            )
+            .with_names(&config.authorization);
+            policy = rules.evaluate(&claims, &local_username, &login_context(pamh));
+        }
+        if policy == Action::Allow && !oauth_client.authorize(&claims, &local_username) {
+            policy = Action::Deny;
+        }
+        let decision = AuthDecision {
+            local_user: local_username.clone(),
+            claims: claims.clone(),
+            policy,
+        };
+        if pamh.set_data(DECISION_KEY, Box::new(decision)).is_err() {
+            log::error!("Failed to keep the authentication decision for acct_mgmt");
+        }
+        match policy {
+            Action::Allow => {}
+            Action::Deny => {
+                log::warn!("Login failed for user: {local_username}");
+                return ErrorClass::Policy.resolve(&config.fail_open);
+            }
+            Action::Ignore => return PamResultCode::PAM_IGNORE,
+        }
+        let remote_username = claims.username();
 
         log::debug!("Remote username: {}", remote_username);
         log::debug!("Local username: {}", local_username);
 
-        if let Err(e) = create_local_user(&remote_username, config.local_group.as_deref()) {
-            log::error!("Could not create user: {}", e);
-            return PamResultCode::PAM_AUTH_ERR;
+        if let Err(e) = create_local_user(&local_username, config.local_group.as_deref()) {
+            return AuthError::new(ErrorClass::Config, "Could not create user", e)
+                .handle(&config.fail_open);
         }
 
-        if oauth_client.validate_token_claims(&token, &remote_username, &local_username) {
-            log::info!(
-                "Authentication successful for remote user: {} -> local user: {}",
-                remote_username,
-                local_username
-            );
+        // A reused authentication has no tokens of its own
+        if let Some(token) = &token {
+            if let Some(key) = &grace_key {
+                if let Err(e) = grace.record(key, &claims, config.grace_period, Utc::now().timestamp()) {
+                    log::warn!("Failed to record grace period: {:#}", e);
+                }
+            }
+            // Without a new one the stored refresh token stays valid
+            let mut refresh_token_stored = false;
+            if let (Some(service), Some(refresh_token)) = (&refresh_service, token.refresh_token()) {
+                let stored = StoredToken {
+                    refresh_token: refresh_token.secret().clone(),
+                    sub: claims.sub().map(str::to_string),
+                };
+                let store = refresh_token_store(&config);
+                match store.save(&local_username, service, &stored, Utc::now().timestamp()) {
+                    Ok(()) => refresh_token_stored = true,
+                    Err(e) => log::warn!("Failed to store refresh token: {:#}", e),
+                }
+            }
+            if config.token_cache.is_some() || config.revoke_on_close {
+                let mut tokens = CachedTokens::new(token, &claims, oauth_client.config(), Utc::now().timestamp());
+                tokens.refresh_token_stored = refresh_token_stored;
+                if pamh.set_data(TOKENS_KEY, Box::new(tokens)).is_err() {
+                    log::error!("Failed to keep the tokens for sm_setcred and sm_close_session");
+                }
+            }
+        }
+
+        log::info!(
+            "Authentication successful for remote user: {} -> local user: {}",
+            remote_username,
+            local_username
+        );
+        PamResultCode::PAM_SUCCESS
+    }
+
+    fn sm_setcred(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+        let config = pam_try!(setup(&args));
+        let Some(template) = &config.token_cache else {
             return PamResultCode::PAM_SUCCESS;
+        };
+        let local_username = pam_try!(pamh.get_user(None));
+        let cache = match users::get_user_by_name(&local_username)
+            .context("No such user")
+            .and_then(|user| TokenCache::new(template, &local_username, user.uid(), user.primary_group_id()))
+        {
+            Ok(cache) => cache,
+            Err(e) => {
+                log::error!("Failed to locate the token cache of {}: {:#}", local_username, e);
+                return PamResultCode::PAM_CRED_ERR;
+            }
+        };
+
+        if flags & PAM_DELETE_CRED != 0 {
+            return match cache.remove() {
+                Ok(()) => {
+                    log::info!("Removed token cache {}", cache.path().display());
+                    PamResultCode::PAM_SUCCESS
+                }
+                Err(e) => {
+                    log::error!("{:#}", e);
+                    PamResultCode::PAM_CRED_ERR
+                }
+            };
         }
 
-        log::warn!("Login failed for user: {local_username}");
+        // Always CachedTokens, set when sm_authenticate succeeded in this transaction
+        let tokens = match unsafe { pamh.get_data::<CachedTokens>(TOKENS_KEY) } {
+            Ok(tokens) => tokens,
+            Err(_) => {
+                log::info!("No tokens from this module to cache for {local_username}");
+                return PamResultCode::PAM_IGNORE;
+            }
+        };
+        match cache.write(tokens) {
+            Ok(()) => {
+                log::info!("Wrote token cache {}", cache.path().display());
+                PamResultCode::PAM_SUCCESS
+            }
+            Err(e) => {
+                log::error!("Failed to write token cache: {:#}", e);
+                PamResultCode::PAM_CRED_ERR
+            }
+        }
+    }
 
-        PamResultCode::PAM_AUTH_ERR
+    fn acct_mgmt(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        let config = pam_try!(setup(&args));
+        let local_username = pam_try!(pamh.get_user(None));
+
+        // Always an AuthDecision, set when sm_authenticate ran in this transaction.
+        // Logins authenticated another way, e.g. with an SSH key, are left to
+        // the rest of the stack.
+        let decision = match unsafe { pamh.get_data::<AuthDecision>(DECISION_KEY) } {
+            Ok(decision) => decision,
+            Err(_) => {
+                log::info!("User {local_username} was not authenticated by this module");
+                return PamResultCode::PAM_IGNORE;
+            }
+        };
+        if decision.local_user != local_username {
+            log::warn!(
+                "User {} was authenticated as {}. Access denied.",
+                local_username,
+                decision.local_user
+            );
+            return PamResultCode::PAM_PERM_DENIED;
+        }
+
+        match decision.status(&config, Utc::now().timestamp()) {
+            AccountStatus::Valid => {
+                log::info!(
+                    "Account valid for remote user: {} -> local user: {}",
+                    decision.claims.username(),
+                    local_username
+                );
+                PamResultCode::PAM_SUCCESS
+            }
+            AccountStatus::Denied => ErrorClass::Policy.resolve(&config.fail_open),
+            AccountStatus::Ignored => PamResultCode::PAM_IGNORE,
+            AccountStatus::Expired => {
+                notify(pamh, &config.messages.account_expired);
+                PamResultCode::PAM_ACCT_EXPIRED
+            }
+            AccountStatus::PasswordExpired => {
+                notify(pamh, &config.messages.password_expired);
+                PamResultCode::PAM_NEW_AUTHTOK_REQD
+            }
+        }
+    }
+
+    fn sm_chauthtok(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        PamResultCode::PAM_IGNORE
     }
+    fn sm_open_session(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        let config = pam_try!(setup(&args));
+        if config.session_env.claims.is_empty() {
+            return PamResultCode::PAM_IGNORE;
+        }
+        let decision = match unsafe { pamh.get_data::<AuthDecision>(DECISION_KEY) } {
+            Ok(decision) if decision.policy == Action::Allow => decision,
+            _ => {
+                log::info!("No identity authenticated by this module to export");
+                return PamResultCode::PAM_IGNORE;
+            }
+        };
 
-    fn sm_setcred(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        let exports = config.session_env.exports(&decision.claims);
+        for (name, value) in &exports {
+            if let Err(code) = putenv(pamh, name, value) {
+                log::error!("Failed to export {} into the PAM environment: {:?}", name, code);
+                return PamResultCode::PAM_SESSION_ERR;
+            }
+        }
+        log::info!(
+            "Exported {} into the session of {}",
+            exports.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "),
+            decision.local_user
+        );
         PamResultCode::PAM_SUCCESS
     }
+    fn sm_close_session(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        let config = pam_try!(setup(&args));
+        if !config.grace_period.is_zero() {
+            if let Some(key) = pamh.get_user(None).ok().and_then(|user| grace_key(pamh, &user)) {
+                if let Err(e) = GraceCache::new(&config.grace_dir).close(&key) {
+                    log::warn!("Failed to remove grace entry of {}: {:#}", key.user, e);
+                }
+            }
+        }
+        if !config.revoke_on_close {
+            return PamResultCode::PAM_IGNORE;
+        }
+        // Always CachedTokens, set when sm_authenticate succeeded in this transaction
+        let tokens = match unsafe { pamh.get_data::<CachedTokens>(TOKENS_KEY) } {
+            Ok(tokens) => tokens,
+            Err(_) => {
+                log::info!("No tokens from this module to revoke");
+                return PamResultCode::PAM_IGNORE;
+            }
+        };
 
-    fn acct_mgmt(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+        // Failures are only logged, logging out must not depend on the provider.
+        // Nothing is discovered and every request gets the short timeout.
+        let mut config = config;
+        config.http_timeout = config.revocation_timeout;
+        config.http_connect_timeout = config.http_connect_timeout.min(config.revocation_timeout);
+        let oauth_client = match OAuthClient::without_discovery(&config) {
+            Ok(oauth_client) => oauth_client,
+            Err(e) => {
+                log::warn!("Failed to revoke tokens of {}: {:#}", tokens.username, e);
+                return PamResultCode::PAM_SUCCESS;
+            }
+        };
+        // A refresh token kept for silent reauthentication must stay valid
+        let refresh_token = tokens.refresh_token.as_ref().filter(|_| !tokens.refresh_token_stored);
+        let revocations = [
+            (refresh_token, "refresh_token"),
+            (Some(&tokens.access_token), "access_token"),
+        ];
+        for (token, hint) in revocations {
+            let Some(token) = token else { continue };
+            match oauth_client.revoke(token, hint) {
+                Ok(()) => log::info!("Revoked {} of {}", hint, tokens.username),
+                Err(e) => log::warn!("Failed to revoke {} of {}: {:#}", hint, tokens.username, e),
+            }
+        }
         PamResultCode::PAM_SUCCESS
     }
+}
 
-    fn sm_chauthtok(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+/// Polls the token endpoint in a background thread and relays its progress
+/// as `PAM_TEXT_INFO` messages. With `auto_poll` polling already runs while
+/// the prompt waits for the user, so an approval given before the user
+/// answers is picked up at once, and answering `q` or `c` cancels it.
+fn poll_interactively(
+    conv: &Conv,
+    oauth_client: &OAuthClient,
+    details: &StandardDeviceAuthorizationResponse,
+    prompt: &str,
+    config: &Config,
+) -> PamResult<Result<DeviceTokenResponse, PollError>> {
+    let cancel = CancelFlag::new();
+    let (status_tx, status_rx) = mpsc::channel();
+    let poller = Poller::new(details, config.oauth_device_token_polling_timeout)
+        .with_cancel(cancel.clone())
+        .with_status(status_tx, config.status_interval);
+
+    if !config.auto_poll {
+        conv.send(PAM_PROMPT_ECHO_OFF, prompt)?;
     }
-    fn sm_open_session(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+
+    std::thread::scope(|s| {
+        let polling = s.spawn(move || oauth_client.poll(details, poller));
+
+        let mut answer = Ok(None);
+        if config.auto_poll {
+            answer = conv.send(PAM_PROMPT_ECHO_OFF, prompt);
+            let cancelled = match &answer {
+                Ok(Some(input)) => is_cancel(input),
+                Ok(None) => false,
+                Err(_) => true,
+            };
+            if cancelled {
+                log::info!("User cancelled the login");
+                cancel.cancel();
+            }
+            // Updates sent while the prompt was shown are out of date
+            while status_rx.try_recv().is_ok() {}
+        }
+
+        // Ends once polling is over and the poller, with its sender, is dropped
+        for remaining in status_rx.iter() {
+            let _ = conv.send(PAM_TEXT_INFO, &config.messages.waiting(remaining));
+        }
+
+        let polled = polling.join().unwrap_or_else(|_| {
+            Err(PollError::request(anyhow::anyhow!("Polling thread panicked")))
+        });
+        answer.map(|_| polled)
+    })
+}
+
+/// Starts logging and reads the config named in the module arguments.
+fn setup(args: &[&CStr]) -> PamResult<Config> {
+    let args = parse_args(args);
+    let default_log_path = "/tmp/pam_oauth2_device.log".to_string();
+    let default_log_level = "info".to_string();
+    let log_path = args.get("logs").unwrap_or(&default_log_path);
+    let log_level = args.get("log_level").unwrap_or(&default_log_level);
+    DefaultLogger::init(log_path, log_level);
+
+    let default_config_path = "/etc/pam_oauth2_device/config.json".to_string();
+    let config_path = args.get("config").unwrap_or(&default_config_path);
+    // Without a config there is no fail_open policy to apply
+    read_config(config_path).map_err(|e| {
+        DefaultLogger::handle_error(e.into(), "Failed to parse config file");
+        PamResultCode::PAM_SYSTEM_ERR
+    })
+}
+
+fn notify(pamh: &PamHandle, message: &str) {
+    if let Ok(Some(conv)) = pamh.get_item::<Conv>() {
+        let _ = conv.send(PAM_ERROR_MSG, message);
     }
-    fn sm_close_session(
-        _pamh: &mut PamHandle,
-        _args: Vec<&CStr>,
-        _flags: PamFlag,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+}
+
+extern "C" {
+    fn pam_putenv(pamh: *mut PamHandle, name_value: *const c_char) -> c_int;
+}
+
+// pam-bindings has no wrapper for the PAM environment. Like its own calls,
+// this goes through a shared reference to the handle.
+fn putenv(pamh: &PamHandle, name: &str, value: &str) -> Result<(), c_int> {
+    let name_value = CString::new(format!("{}={}", name, value)).map_err(|_| -1)?;
+    let handle = pamh as *const PamHandle as *mut PamHandle;
+    match unsafe { pam_putenv(handle, name_value.as_ptr()) } {
+        0 => Ok(()),
+        code => Err(code),
     }
 }
 
+fn refresh_token_store(config: &Config) -> RefreshTokenStore {
+    RefreshTokenStore::new(
+        config.cache_dir.join("refresh_tokens"),
+        &config.client_id,
+        config.refresh_token_max_age,
+    )
+}
+
+/// Tries the refresh token stored for `user` and `service`. The new token goes
+/// through the same validation as a device-flow token and must be issued to
+/// the same subject as the stored one. The stored token is dropped when the
+/// provider refuses it as `invalid_grant` or the new token fails these
+/// checks, but kept when the provider is unavailable.
+fn refresh_silently(
+    oauth_client: &OAuthClient,
+    config: &Config,
+    user: &str,
+    service: &str,
+) -> Option<(VerifiedClaims, DeviceTokenResponse)> {
+    let store = refresh_token_store(config);
+    let stored = match store.load(user, service, Utc::now().timestamp()) {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return None,
+        Err(e) => {
+            log::warn!("Ignoring stored refresh token of {}: {:#}", user, e);
+            let _ = store.remove(user, service);
+            return None;
+        }
+    };
+
+    let token = match oauth_client.refresh(&stored.refresh_token) {
+        Ok(token) => token,
+        // Only a refused grant is final, the provider may just be unreachable
+        Err(e) => {
+            let invalid = e.downcast_ref::<RefreshRejected>().is_some_and(RefreshRejected::is_invalid_grant);
+            if invalid {
+                log::warn!("Refresh token of {} was refused, falling back to the device flow: {:#}", user, e);
+                let _ = store.remove(user, service);
+            } else {
+                log::warn!("Refresh token of {} could not be used, falling back to the device flow: {:#}", user, e);
+            }
+            return None;
+        }
+    };
+    let verified = oauth_client.verify(&token).and_then(|claims| {
+        if claims.sub() != stored.sub.as_deref() {
+            anyhow::bail!("Subject {:?} does not match {:?} of the stored token", claims.sub(), stored.sub);
+        }
+        Ok(claims)
+    });
+    match verified {
+        Ok(claims) => {
+            log::info!("Reauthenticated {} with a refresh token", user);
+            Some((claims, token))
+        }
+        Err(e) => {
+            log::warn!("Refreshed token of {} failed validation, falling back to the device flow: {:#}", user, e);
+            let _ = store.remove(user, service);
+            None
+        }
+    }
+}
+
+fn grace_key(pamh: &PamHandle, user: &str) -> Option<GraceKey> {
+    let service = pamh.get_item::<Service>().ok().flatten()?;
+    let tty = pamh.get_item::<Tty>().ok().flatten();
+    GraceKey::current(
+        user,
+        &service.0.to_string_lossy(),
+        tty.map(|t| t.0.to_string_lossy().into_owned()).as_deref(),
+    )
+}
+
+fn login_context(pamh: &PamHandle) -> LoginContext {
+    let to_string = |value: &CStr| value.to_string_lossy().into_owned();
+    LoginContext {
+        service: pamh.get_item::<Service>().ok().flatten().map(|s| to_string(s.0)),
+        hostname: LoginContext::local_hostname(),
+        rhost: pamh.get_item::<RHost>().ok().flatten().map(|r| to_string(r.0)),
+    }
+}
+
+fn is_cancel(input: &CStr) -> bool {
+    matches!(
+        input.to_string_lossy().trim().to_ascii_lowercase().as_str(),
+        "q" | "c"
+    )
+}
+
 fn parse_args(args: &[&CStr]) -> HashMap<String, String> {
     args.iter()
         .map(|&s| {
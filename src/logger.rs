@@ -51,7 +51,11 @@ impl DefaultLogger {
         });
     }
 
-    // Shutdowns global logger
+    /// Shutdowns global logger
+    ///
+    /// # Safety
+    ///
+    /// Must only be called once no other code can log anymore, i.e. right before the module is unloaded.
     pub unsafe fn shutdown() {
         let logger_ptr = log::logger() as *const dyn Log;
         if !logger_ptr.is_null() {
@@ -1,83 +1,185 @@
 use std::time::Duration;
 
-use crate::config::Config;
-use oauth2::basic::BasicClient;
-use oauth2::curl::http_client;
-use oauth2::devicecode::StandardDeviceAuthorizationResponse;
-use oauth2::{AccessToken, AuthUrl, ClientId, ClientSecret, DeviceAuthorizationUrl, RedirectUrl, Scope, TokenUrl};
-
-use serde::Deserialize;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use reqwest::blocking::{get, Client};
-use anyhow::{Result, bail};
-use base64;
-use base64::engine::general_purpose::URL_SAFE;
-use base64::Engine;
-use serde_json::Value;
-use std::collections::HashMap;
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Audience {
-    Single(String),
-    Multiple(Vec<String>),
+use crate::access_rules::AccessRules;
+use crate::account_map::AccountMap;
+use crate::claims::VerifiedClaims;
+use crate::client_auth::{ClientAssertion, ClientAuthMethod, CLIENT_ASSERTION_TYPE};
+use crate::config::{Config, TokenValidation};
+use crate::discovery;
+use crate::graph;
+use crate::poller::{retry_after, PollError, Poller};
+use crate::http::HttpClient;
+use crate::jwks::JwksCache;
+use oauth2::basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenType};
+use oauth2::devicecode::{DeviceCodeErrorResponse, StandardDeviceAuthorizationResponse};
+use oauth2::{
+    AccessToken, AuthType, AuthUrl, Client as OAuth2Client, ClientId, ClientSecret, DeviceAuthorizationUrl,
+    ExtraTokenFields, IntrospectionUrl, RedirectUrl, Scope,
+    StandardRevocableToken, StandardTokenIntrospectionResponse, StandardTokenResponse,
+    TokenIntrospectionResponse, TokenResponse, TokenUrl,
+};
+
+use serde::{Deserialize, Serialize};
+use jsonwebtoken::{decode, Algorithm, Validation};
+use reqwest::blocking::RequestBuilder;
+use reqwest::StatusCode;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde_json::{Map, Value};
+use url::Url;
+
+/// Token endpoint fields beyond RFC 6749 that the module relies on.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IdTokenFields {
+    #[serde(default)]
+    pub id_token: Option<String>,
 }
+impl ExtraTokenFields for IdTokenFields {}
 
-#[derive(Debug, Deserialize)]
-struct Claims {
-    sub: String,
-    email: Option<String>,
-    preferred_username: Option<String>,
-    aud: Option<Audience>,
-    groups: Option<Vec<String>>,
+/// Introspection response fields beyond RFC 7662 used for authorization.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IntrospectionFields {
+    #[serde(default)]
+    pub groups: Option<Vec<String>>,
+    /// Remaining members such as `cnf`, `acr` or `amr`, kept for claim checks.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
+impl ExtraTokenFields for IntrospectionFields {}
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-struct Jwk {
-    n: String,
-    e: String,
-    #[serde(default)]
-    kty: Option<String>,
-    #[serde(default)]
-    kid: Option<String>,
-    #[serde(default)]
-    alg: Option<String>,
-    #[serde(rename = "use")]
-    use_: Option<String>,
+pub type DeviceTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+pub type IntrospectionResponse = StandardTokenIntrospectionResponse<IntrospectionFields, BasicTokenType>;
+
+type DeviceClient = OAuth2Client<
+    BasicErrorResponse,
+    DeviceTokenResponse,
+    BasicTokenType,
+    IntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+/// Error response of the token endpoint to a refresh request.
+#[derive(Debug)]
+pub struct RefreshRejected {
+    pub status: StatusCode,
+    pub body: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct Jwks {
-    keys: Vec<Jwk>,
+impl RefreshRejected {
+    /// Whether the refresh token itself is invalid, expired or revoked
+    /// (RFC 6749, section 5.2), so it will never work again.
+    pub fn is_invalid_grant(&self) -> bool {
+        serde_json::from_str::<Value>(&self.body)
+            .is_ok_and(|body| body["error"] == "invalid_grant")
+    }
+}
+
+impl std::fmt::Display for RefreshRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Refresh token rejected ({}): {}", self.status, self.body)
+    }
 }
 
+impl std::error::Error for RefreshRejected {}
+
 #[derive(Debug)]
 pub struct OAuthClient {
-    client: BasicClient,
+    client: DeviceClient,
     scopes: Vec<Scope>,
+    token_url: Url,
+    assertion: Option<ClientAssertion>,
+    http: HttpClient,
     config: Config,
 }
 
 impl OAuthClient {
     pub fn new(c: &Config) -> Result<Self> {
-        let client_id = ClientId::new(c.client_id.clone());
-        let client_secret = ClientSecret::new(c.client_secret.clone());
-        let auth_url = AuthUrl::from_url(c.oauth_auth_url.clone());
-        let token_url = TokenUrl::from_url(c.oauth_token_url.clone());
-        let device_url = DeviceAuthorizationUrl::from_url(c.oauth_device_url.clone());
+        let mut config = c.clone();
+        let http = HttpClient::new(&config)?;
+        discovery::resolve(&mut config, &http).context("OpenID Connect discovery failed")?;
+        Self::with_endpoints(config, http)
+    }
+
+    /// Like `new`, but missing endpoints only come from a cached discovery
+    /// document, so nothing is fetched before the first real request.
+    pub fn without_discovery(c: &Config) -> Result<Self> {
+        let mut config = c.clone();
+        let http = HttpClient::new(&config)?;
+        discovery::resolve_cached(&mut config);
+        Self::with_endpoints(config, http)
+    }
+
+    fn with_endpoints(config: Config, http: HttpClient) -> Result<Self> {
+        let client_id = ClientId::new(config.client_id.clone());
+        let auth_method = config.client_auth_method();
+        let client_secret = match auth_method {
+            ClientAuthMethod::ClientSecretBasic | ClientAuthMethod::ClientSecretPost => {
+                Some(ClientSecret::new(config.client_secret.clone().context(
+                    "client_secret is required unless token_endpoint_auth_method is private_key_jwt or none",
+                )?))
+            }
+            _ => None,
+        };
+        if matches!(
+            auth_method,
+            ClientAuthMethod::TlsClientAuth | ClientAuthMethod::SelfSignedTlsClientAuth
+        ) && http.certificate_thumbprint().is_none()
+        {
+            bail!("client_certificate is required for mutual TLS client authentication");
+        }
+        let assertion = match auth_method {
+            ClientAuthMethod::PrivateKeyJwt => Some(ClientAssertion::load(&config)?),
+            _ => None,
+        };
+        let auth_url = AuthUrl::from_url(required_url(&config.oauth_auth_url, "oauth_auth_url")?);
+        let token_url = required_url(&config.oauth_token_url, "oauth_token_url")?;
+        let device_url = DeviceAuthorizationUrl::from_url(required_url(
+            &config.oauth_device_url,
+            "oauth_device_url",
+        )?);
         let redirect_url = RedirectUrl::new("urn:ietf:wg:oauth:2.0:oob".to_string())?;
-        let scopes = c
+        let mut scopes: Vec<Scope> = config
             .scopes
             .split_whitespace()
             .map(|s| Scope::new(s.to_string()))
             .collect();
+        // Most providers only issue refresh tokens for this scope
+        let offline_access = Scope::new("offline_access".to_string());
+        if !config.refresh_token_services.is_empty() && !scopes.contains(&offline_access) {
+            scopes.push(offline_access);
+        }
+
+        let mut client = DeviceClient::new(
+            client_id,
+            client_secret,
+            auth_url,
+            Some(TokenUrl::from_url(token_url.clone())),
+        )
+        .set_auth_type(match auth_method {
+            ClientAuthMethod::ClientSecretBasic => AuthType::BasicAuth,
+            _ => AuthType::RequestBody,
+        })
+        .set_device_authorization_url(device_url)
+        .set_redirect_uri(redirect_url);
+        if let Some(introspect_url) = &config.oauth_token_introspect_url {
+            client = client.set_introspection_uri(IntrospectionUrl::from_url(introspect_url.clone()));
+        } else if config.token_validation == TokenValidation::Introspection {
+            anyhow::bail!("oauth_token_introspect_url is required for introspection validation");
+        }
 
-        let client = BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
-            .set_device_authorization_url(device_url)
-            .set_redirect_uri(redirect_url);
+        Ok(Self {
+            client,
+            scopes,
+            token_url,
+            assertion,
+            http,
+            config,
+        })
+    }
 
-        Ok(Self { client, scopes, config: c.clone() })
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     pub fn scopes(&self) -> &[Scope] {
@@ -85,180 +187,499 @@ impl OAuthClient {
     }
 
     pub fn device_code(&self) -> Result<StandardDeviceAuthorizationResponse, Box<dyn std::error::Error>> {
-        let details: StandardDeviceAuthorizationResponse = self
+        let mut request = self
             .client
             .exchange_device_code()?
-            .add_scopes(self.scopes.clone())
-            .request(http_client)?;
+            .add_scopes(self.scopes.clone());
+        for (name, value) in self.assertion_params()? {
+            request = request.add_extra_param(name, value);
+        }
+        let details: StandardDeviceAuthorizationResponse = request.request(|r| self.http.oauth2(r))?;
         Ok(details)
     }
 
+    /// Polls the token endpoint until the user completes the authorization,
+    /// following RFC 8628, section 3.5.
     pub fn get_token(
         &self,
         details: &StandardDeviceAuthorizationResponse,
         timeout: Option<Duration>,
-    ) -> Result<AccessToken, Box<dyn std::error::Error>> {
-        let client = Client::new();
-        let device_code = details.device_code().secret();
-        let tenant_fallback = "common".to_string();
-        let url = format!(
-            "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
-            self.config.tenant_id.as_ref().unwrap_or(&tenant_fallback)
-        );
-
-        let start = std::time::Instant::now();
-        let poll_interval = details.interval();
-        let max_duration = timeout.unwrap_or_else(|| Duration::from_secs(600));
+    ) -> Result<DeviceTokenResponse, PollError> {
+        self.poll(details, Poller::new(details, timeout))
+    }
 
-        while start.elapsed() < max_duration {
-            let mut params = HashMap::new();
-            params.insert("grant_type", "urn:ietf:params:oauth:grant-type:device_code");
-            params.insert("client_id", &self.config.client_id);
-            params.insert("device_code", device_code);
-            params.insert("client_secret", &self.config.client_secret);
+    /// Like `get_token`, with a schedule set up by the caller, e.g. to
+    /// cancel polling from another thread.
+    pub fn poll(
+        &self,
+        details: &StandardDeviceAuthorizationResponse,
+        mut poller: Poller,
+    ) -> Result<DeviceTokenResponse, PollError> {
+        loop {
+            if let Some(err) = poller.stopped() {
+                return Err(err);
+            }
 
-            let resp = client.post(&url).form(&params).send()?;
+            let resp = self.token_request(details)?.send().map_err(PollError::request)?;
             let status = resp.status();
-            let body: Value = resp.json()?;
-
-            if status == 200 {
-                if let Some(id_token) = body.get("id_token") {
-                    let token = id_token.as_str().ok_or("id_token not a string")?;
-                    return Ok(AccessToken::new(token.to_string()));
-                } else {
-                    return Err(anyhow::anyhow!("Token response missing id_token").into());
+            let retry_after = retry_after(resp.headers());
+            let body = resp.bytes().map_err(PollError::request)?;
+
+            if status.is_success() {
+                return serde_json::from_slice(&body).map_err(PollError::request);
+            }
+            let throttled = status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::SERVICE_UNAVAILABLE;
+            let wait = match serde_json::from_slice::<DeviceCodeErrorResponse>(&body) {
+                Ok(err) => poller.on_error(err, retry_after)?,
+                // Rate limiting by a proxy or gateway rather than the authorization server
+                Err(_) if throttled => {
+                    log::warn!("Token endpoint returned {}, retrying", status);
+                    poller.wait(retry_after)
                 }
-            } else if let Some(err) = body.get("error").and_then(|v| v.as_str()) {
-                if err == "authorization_pending" {
-                    std::thread::sleep(poll_interval);
-                    continue;
-                } else {
-                    return Err(anyhow::anyhow!("OAuth error: {}", err).into());
+                Err(_) => {
+                    return Err(PollError::request(anyhow::anyhow!(
+                        "Unexpected token response {}: {}",
+                        status,
+                        String::from_utf8_lossy(&body)
+                    )))
                 }
-            } else {
-                return Err(anyhow::anyhow!("Unexpected token response: {:?}", body).into());
-            }
+            };
+            poller.report();
+            poller.sleep(wait);
         }
+    }
 
-        return Err(anyhow::anyhow!("Timeout while polling for token").into());
-
+    fn token_request(
+        &self,
+        details: &StandardDeviceAuthorizationResponse,
+    ) -> Result<RequestBuilder, PollError> {
+        let mut params = vec![
+            ("grant_type", "urn:ietf:params:oauth:grant-type:device_code".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("device_code", details.device_code().secret().clone()),
+        ];
+        let request = self
+            .authenticate(self.http.post(self.token_url.clone()), &mut params)
+            .map_err(PollError::request)?;
+        Ok(request.form(&params))
     }
 
-    pub fn validate_token_claims(
+    // Client authentication of `token_endpoint_auth_method` for a request to
+    // the token endpoint, either as a header or as form parameters.
+    fn authenticate(
         &self,
-        token: &AccessToken,
-        remote_username: &str,
-        local_user: &str,
-    ) -> bool {
-        if !valid_user(remote_username, local_user) {
-            return false;
+        mut request: RequestBuilder,
+        params: &mut Vec<(&'static str, String)>,
+    ) -> Result<RequestBuilder> {
+        match (self.config.client_auth_method(), &self.config.client_secret) {
+            (ClientAuthMethod::ClientSecretBasic, Some(secret)) => {
+                // RFC 6749, section 2.3.1: both parts are form-encoded first
+                let encode =
+                    |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+                request = request.basic_auth(encode(&self.config.client_id), Some(encode(secret)));
+            }
+            (ClientAuthMethod::ClientSecretPost, Some(secret)) => {
+                params.push(("client_secret", secret.clone()));
+            }
+            _ => params.extend(self.assertion_params()?),
         }
+        Ok(request)
+    }
 
-        let header = match jsonwebtoken::decode_header(token.secret()) {
-            Ok(h) => h,
-            Err(e) => {
-                log::error!("Failed to decode token header: {}", e);
-                return false;
+    /// Redeems a refresh token (RFC 6749, section 6). The response still has
+    /// to pass `verify` like one from the device flow.
+    pub fn refresh(&self, refresh_token: &str) -> Result<DeviceTokenResponse> {
+        let mut params = vec![
+            ("grant_type", "refresh_token".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("refresh_token", refresh_token.to_string()),
+        ];
+        let request = self.authenticate(self.http.post(self.token_url.clone()), &mut params)?;
+        let resp = request
+            .form(&params)
+            .send()
+            .context("Refresh request failed")?;
+        let status = resp.status();
+        let body = resp.bytes().context("Refresh request failed")?;
+        if !status.is_success() {
+            return Err(RefreshRejected {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
             }
-        };
+            .into());
+        }
+        serde_json::from_slice(&body).context("Malformed refresh token response")
+    }
 
-        let kid = match header.kid {
-            Some(k) => k,
-            None => {
-                log::error!("No kid in token header");
-                return false;
-            }
-        };
+    /// Revokes `token` at `revocation_url` (RFC 7009). `hint` is
+    /// `refresh_token` or `access_token`.
+    pub fn revoke(&self, token: &str, hint: &str) -> Result<()> {
+        let url = self
+            .config
+            .revocation_url
+            .clone()
+            .context("No revocation_url configured or discovered")?;
+        let mut params = vec![
+            ("token", token.to_string()),
+            ("token_type_hint", hint.to_string()),
+            ("client_id", self.config.client_id.clone()),
+        ];
+        let request = self.authenticate(self.http.post(url), &mut params)?;
+        request
+            .form(&params)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .context("Revocation request failed")?;
+        Ok(())
+    }
+
+    /// App-only access token for `scope` from the client credentials grant,
+    /// authenticated like every other token request.
+    fn client_credentials_token(&self, scope: &str) -> Result<String> {
+        let mut params = vec![
+            ("grant_type", "client_credentials".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("scope", scope.to_string()),
+        ];
+        let request = self.authenticate(self.http.post(self.token_url.clone()), &mut params)?;
+
+        let resp: Value = request
+            .form(&params)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .context("Client credentials request failed")?
+            .json()
+            .context("Malformed client credentials response")?;
+        resp.get("access_token")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .context("No access_token in client credentials response")
+    }
 
-        let tenant_fallback = "common".to_string();
-        let jwks_url = format!(
-            "https://login.microsoftonline.com/{}/discovery/v2.0/keys",
-            self.config.tenant_id.as_ref().unwrap_or(&tenant_fallback)
+    /// Looks up the groups Azure left out of the token in Microsoft Graph.
+    fn resolve_group_overage(&self, claims: &VerifiedClaims) -> Result<Vec<String>> {
+        let user_id = claims
+            .get_str("oid")
+            .context("No 'oid' claim to look up group membership for")?;
+        let access_token = self.client_credentials_token(&graph::scope(&self.config.graph_url))?;
+        let groups = graph::member_objects(&self.http, &self.config.graph_url, &access_token, user_id)?;
+        log::info!(
+            "Groups claim overage for user {}, resolved {} groups in Microsoft Graph",
+            claims.username(),
+            groups.len()
         );
-        let jwks: Jwks = match get(&jwks_url).and_then(|r| r.json()) {
-            Ok(k) => k,
-            Err(e) => {
-                log::error!("Failed to fetch JWKS: {}", e);
-                return false;
+        Ok(groups)
+    }
+
+    /// Validates the token response according to `token_validation` and returns
+    /// its verified claims. Nothing about the user is read from the token elsewhere.
+    pub fn verify(&self, token: &DeviceTokenResponse) -> Result<VerifiedClaims> {
+        match self.config.token_validation {
+            TokenValidation::Jwt => {
+                let id_token = token
+                    .extra_fields()
+                    .id_token
+                    .clone()
+                    .map(AccessToken::new)
+                    .context("Token response missing id_token")?;
+                let claims = self.validate_token_claims(&id_token)?;
+                self.check_access_token_binding(token.access_token())?;
+                Ok(claims)
             }
-        };
+            TokenValidation::Introspection => {
+                let introspection = self
+                    .request_introspection(token.access_token())
+                    .context("Failed to introspect user token")?;
+                log::debug!("Introspection response: {:#?}", introspection);
+                self.introspection_claims(&introspection)
+            }
+        }
+    }
 
-        let jwk = match jwks.keys.into_iter().find(|j| j.kid.as_deref() == Some(&kid)) {
-            Some(j) => j,
-            None => {
-                log::error!("No matching key for kid {}", kid);
-                return false;
+    /// Decides whether the verified identity may log in as `local_user`.
+    pub fn authorize(&self, claims: &VerifiedClaims, local_user: &str) -> bool {
+        self.authorize_account(claims, local_user)
+            && self.authorize_groups(claims.groups())
+            && self.config.authorization.authorize(claims)
+    }
+
+    // Without an `account_map` the remote username must equal the local one.
+    fn authorize_account(&self, claims: &VerifiedClaims, local_user: &str) -> bool {
+        let path = match &self.config.account_map {
+            Some(path) => path,
+            None => return valid_user(claims.username(), local_user),
+        };
+        match AccountMap::load(path) {
+            Ok(map) => map.authorize(claims, local_user),
+            Err(e) => {
+                log::error!("Failed to load account map: {:#}", e);
+                false
             }
+        }
+    }
+
+    pub fn validate_token_claims(&self, token: &AccessToken) -> Result<VerifiedClaims> {
+        let claims = self.decode_jwt(token, Some(&self.config.client_id))?;
+        let mut claims = VerifiedClaims::new(
+            claims,
+            &self.config.username_claims(TokenValidation::Jwt),
+            &self.config.username_transforms,
+        )?;
+        self.config.claim_requirements.check(&claims)?;
+        if self.needs_groups() && graph::has_group_overage(&claims) {
+            let groups = self
+                .resolve_group_overage(&claims)
+                .context("Failed to resolve group overage")?;
+            claims.set_groups(groups);
+        }
+
+        log::info!("Token validated successfully for user: {}", claims.username());
+        Ok(claims)
+    }
+
+    // Group overage is only worth a Graph lookup when some check reads groups.
+    // A file that fails to load is assumed to, and fails authorization later.
+    fn needs_groups(&self) -> bool {
+        let config = &self.config;
+        config.allowed_groups.is_some()
+            || config.authorization.uses_groups()
+            || config
+                .account_map
+                .as_deref()
+                .is_some_and(|path| AccountMap::load(path).map_or(true, |map| map.uses_groups()))
+            || config
+                .access_rules
+                .as_deref()
+                .is_some_and(|path| AccessRules::load(path).map_or(true, |rules| rules.uses_groups()))
+    }
+
+    /// Claims of a JWT signed by a key of the provider and issued by an
+    /// accepted issuer. Access tokens are meant for a resource server, so
+    /// their audience is not checked when `audience` is `None`.
+    fn decode_jwt(&self, token: &AccessToken, audience: Option<&str>) -> Result<Map<String, Value>> {
+        let header =
+            jsonwebtoken::decode_header(token.secret()).context("Failed to decode token header")?;
+
+        if !algorithm_allowed(header.alg, &self.config.allowed_algorithms) {
+            bail!("Token signed with disallowed algorithm {:?}", header.alg);
+        }
+
+        let kid = header.kid.as_deref().context("No kid in token header")?;
+        let jwks_url = self
+            .config
+            .jwks_url()
+            .with_context(|| format!("No JWKS URL configured for provider {:?}", self.config.provider()))?;
+        let jwk = JwksCache::new(&self.config, &self.http, jwks_url)
+            .find(kid)
+            .context("Failed to get signing key")?;
+        let decoding_key = jwk
+            .decoding_key(header.alg)
+            .context("Failed to create decoding key")?;
+
+        let issuers = self.config.issuers();
+        if issuers.is_empty() {
+            bail!("No accepted issuer configured for provider {:?}", self.config.provider());
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&issuers);
+        match audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation.leeway = self.config.claim_requirements.leeway.as_secs();
+        validation.validate_nbf = true;
+
+        let token_data = decode::<Map<String, Value>>(token.secret(), &decoding_key, &validation)
+            .context("Failed to decode JWT")?;
+        Ok(token_data.claims)
+    }
+
+    pub fn introspect(
+        &self,
+        token: &AccessToken,
+    ) -> Result<IntrospectionResponse, Box<dyn std::error::Error>> {
+        Ok(self.request_introspection(token)?)
+    }
+
+    fn request_introspection(&self, token: &AccessToken) -> Result<IntrospectionResponse> {
+        let mut request = self.client.introspect(token)?;
+        for (name, value) in self.assertion_params()? {
+            request = request.add_extra_param(name, value);
+        }
+        let resp = request.request(|r| self.http.oauth2(r))?;
+        Ok(resp)
+    }
+
+    // A fresh assertion per request, as servers may reject a reused `jti`.
+    fn assertion_params(&self) -> Result<Vec<(&'static str, String)>> {
+        let assertion = match &self.assertion {
+            Some(assertion) => assertion,
+            None => return Ok(Vec::new()),
         };
+        Ok(vec![
+            ("client_assertion_type", CLIENT_ASSERTION_TYPE.to_string()),
+            ("client_assertion", assertion.sign(&self.token_url)?),
+        ])
+    }
 
-        let decoding_key = match DecodingKey::from_rsa_components(&jwk.n, &jwk.e) {
-            Ok(k) => k,
+    /// Checks an RFC 7662 introspection response and authorizes it for `local_user`.
+    pub fn validate_token(&self, token: &IntrospectionResponse, local_user: &str) -> bool {
+        match self.introspection_claims(token) {
+            Ok(claims) => self.authorize(&claims, local_user),
             Err(e) => {
-                log::error!("Failed to create decoding key: {}", e);
-                return false;
+                log::warn!("{:#}", e);
+                false
             }
+        }
+    }
+
+    fn introspection_claims(&self, token: &IntrospectionResponse) -> Result<VerifiedClaims> {
+        if !token.active() {
+            bail!("User token inactive!");
+        }
+
+        let claims = match serde_json::to_value(token)? {
+            Value::Object(claims) => claims,
+            _ => bail!("Malformed introspection response"),
         };
+        let claims = VerifiedClaims::new(
+            claims,
+            &self.config.username_claims(TokenValidation::Introspection),
+            &self.config.username_transforms,
+        )?;
+        let remote_username = claims.username();
+
+        let token_scopes = token.scopes().context("No scope provided in token")?;
+        if !self.scopes.iter().all(|s| token_scopes.contains(s)) {
+            bail!(
+                "Insuficient scopes for user {}: {:?}",
+                remote_username,
+                token_scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>()
+            );
+        }
 
-        let mut validation = Validation::new(Algorithm::RS256);
-        let tenant_id = self.config.tenant_id.as_deref().unwrap_or("common");
-        validation.set_issuer(&[
-            &format!("https://login.microsoftonline.com/{}/v2.0", tenant_id),
-            &format!("https://sts.windows.net/{}/", tenant_id),
-        ]);
-        validation.set_audience(&[&self.config.client_id]);
+        if let Some(client_id) = token.client_id() {
+            if client_id.as_str() != self.config.client_id {
+                bail!("Token was issued to another client: {}", client_id.as_str());
+            }
+        }
 
-        let token_data = match decode::<Claims>(token.secret(), &decoding_key, &validation) {
-            Ok(data) => data,
-            Err(e) => {
-                log::error!("Failed to decode JWT: {}", e);
-                return false;
+        let leeway = chrono::Duration::from_std(self.config.claim_requirements.leeway)?;
+        match token.exp() {
+            Some(exp) if exp + leeway <= Utc::now() => {
+                bail!("Token has expired for user {}", remote_username)
             }
-        };
+            Some(_) => {}
+            None => bail!("No expiration time provided in token"),
+        }
 
-        log::info!("Token validated successfully for user: {:?}", token_data.claims.preferred_username);
-        
-        if let Some(allowed_groups) = &self.config.allowed_groups {
-            if let Some(groups) = &token_data.claims.groups {
-                let authorized = groups.iter().any(|g| allowed_groups.contains(g));
-                if !authorized {
-                    log::warn!("User not in any allowed Azure AD group. Access denied.");
-                    return false;
-                } else {
-                    log::info!("User is authorized based on group membership: {:?}", groups);
-                }
-            } else {
-                log::warn!("No 'groups' claim present in token, but 'allowed_groups' is configured. Access denied.");
-                return false;
+        self.config.claim_requirements.check(&claims)?;
+        self.check_certificate_binding(claims.get("cnf"))?;
+
+        log::info!("Token introspected successfully for user: {}", claims.username());
+        Ok(claims)
+    }
+
+    // RFC 8705 binds the access token, not the id_token. Unless a client
+    // certificate is configured or binding is required there is nothing to
+    // check. Access tokens for other resources, such as Microsoft Graph,
+    // cannot always be verified, so unless binding is required one that
+    // cannot be read is accepted as unbound.
+    fn check_access_token_binding(&self, token: &AccessToken) -> Result<()> {
+        let required = self.config.require_certificate_bound_tokens;
+        if self.http.certificate_thumbprint().is_none() && !required {
+            return Ok(());
+        }
+        let claims = match self.access_token_claims(token) {
+            Ok(claims) => claims,
+            Err(e) if !required => {
+                log::debug!("Not checking the certificate binding of the access token: {:#}", e);
+                return Ok(());
             }
+            Err(e) => return Err(e),
+        };
+        if claims.get("active").is_some_and(|active| *active != Value::Bool(true)) {
+            bail!("Access token inactive");
         }
+        self.check_certificate_binding(claims.get("cnf"))
+    }
 
-        true
+    /// Claims of a JWT access token, or its introspection response when it
+    /// is opaque.
+    fn access_token_claims(&self, token: &AccessToken) -> Result<Map<String, Value>> {
+        if token.secret().split('.').count() == 3 {
+            return self.decode_jwt(token, None).context("Failed to verify access token");
+        }
+        if self.config.oauth_token_introspect_url.is_none() {
+            bail!("Access token is opaque and no oauth_token_introspect_url is configured to check its certificate binding");
+        }
+        let introspection = self
+            .request_introspection(token)
+            .context("Failed to introspect access token")?;
+        match serde_json::to_value(introspection)? {
+            Value::Object(claims) => Ok(claims),
+            _ => bail!("Malformed introspection response"),
+        }
     }
 
-    pub fn introspect_username(&self, token: &AccessToken) -> Result<String> {
-        self.validate_token_claims(token, "dummy", "dummy");
-        let parts: Vec<&str> = token.secret().split('.').collect();
-        if parts.len() != 3 {
-            return Err(anyhow::anyhow!("Invalid JWT structure").into());
+    // RFC 8705, section 3: a certificate-bound token is only accepted from the
+    // client holding that certificate.
+    fn check_certificate_binding(&self, cnf: Option<&Value>) -> Result<()> {
+        let bound = cnf
+            .and_then(|cnf| cnf.get("x5t#S256"))
+            .and_then(Value::as_str);
+        match (bound, self.http.certificate_thumbprint()) {
+            (Some(bound), Some(presented)) if bound == presented => Ok(()),
+            (Some(bound), presented) => bail!(
+                "Claim 'cnf.x5t#S256' is {}, but the client certificate is {}",
+                bound,
+                presented.unwrap_or("not configured")
+            ),
+            (None, _) if self.config.require_certificate_bound_tokens => {
+                bail!("Claim 'cnf.x5t#S256' is missing, token is not certificate-bound")
+            }
+            (None, _) => Ok(()),
         }
-        let payload = base64::engine::general_purpose::URL_SAFE.decode(pad_base64(parts[1]))?;
-        let json: Value = serde_json::from_slice(&payload)?;
-        if let Some(username) = json.get("preferred_username").or_else(|| json.get("email")) {
-            Ok(username.as_str().unwrap_or("anonymous").to_string())
-        } else {
-            return Err(anyhow::anyhow!("User name not found in token claims").into());
+    }
+
+    fn authorize_groups(&self, groups: Option<&[String]>) -> bool {
+        let allowed_groups = match &self.config.allowed_groups {
+            Some(allowed_groups) => allowed_groups,
+            None => return true,
+        };
+
+        let authorization = &self.config.authorization;
+        match groups {
+            Some(groups)
+                if allowed_groups
+                    .iter()
+                    .any(|allowed| groups.iter().any(|g| g == authorization.resolve(allowed))) =>
+            {
+                log::info!("User is authorized based on group membership: {:?}", groups);
+                true
+            }
+            Some(_) => {
+                log::warn!("User not in any allowed group. Access denied.");
+                false
+            }
+            None => {
+                log::warn!("No 'groups' claim present in token, but 'allowed_groups' is configured. Access denied.");
+                false
+            }
         }
     }
 }
 
-fn pad_base64(input: &str) -> String {
-    let rem = input.len() % 4;
-    if rem == 0 {
-        input.to_string()
-    } else {
-        let padding = 4 - rem;
-        format!("{}{}", input, "=".repeat(padding))
-    }
+fn algorithm_allowed(alg: Algorithm, allowed: &[Algorithm]) -> bool {
+    let symmetric = matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512);
+    !symmetric && allowed.contains(&alg)
+}
+
+fn required_url(url: &Option<Url>, name: &str) -> Result<Url> {
+    url.clone()
+        .with_context(|| format!("{} is not configured and was not discovered", name))
 }
 
 fn valid_user(remote_username: &str, local_username: &str) -> bool {
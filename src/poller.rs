@@ -0,0 +1,243 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use oauth2::devicecode::{DeviceCodeErrorResponse, DeviceCodeErrorResponseType};
+use oauth2::StandardDeviceAuthorizationResponse;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::config::Messages;
+
+// RFC 8628, section 3.5: "the interval MUST be increased by 5 seconds"
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+
+// How quickly a sleeping poller notices that it was cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Bounds delays taken from the server, which could otherwise overflow
+// `Instant` arithmetic
+const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Why polling the token endpoint ended without a token.
+#[derive(Debug)]
+pub enum PollError {
+    /// The user or the server denied the authorization request.
+    AccessDenied(DeviceCodeErrorResponse),
+    /// The device code expired, either reported by the server or because
+    /// its `expires_in` passed.
+    ExpiredToken,
+    /// `oauth_device_token_polling_timeout` passed before the device code expired.
+    Timeout,
+    /// The user cancelled the login while polling.
+    Cancelled,
+    /// Any other error response of the token endpoint.
+    Server(DeviceCodeErrorResponse),
+    /// The request failed or the response could not be read.
+    Request(Box<dyn Error + Send + Sync>),
+}
+
+impl PollError {
+    pub fn request<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> Self {
+        PollError::Request(err.into())
+    }
+
+    /// Text shown to the user for this outcome.
+    pub fn user_message<'a>(&self, messages: &'a Messages) -> &'a str {
+        match self {
+            PollError::AccessDenied(_) => &messages.poll_access_denied,
+            PollError::ExpiredToken => &messages.poll_expired,
+            PollError::Timeout => &messages.poll_timeout,
+            PollError::Cancelled => &messages.poll_cancelled,
+            PollError::Server(_) | PollError::Request(_) => &messages.poll_failed,
+        }
+    }
+}
+
+impl fmt::Display for PollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PollError::AccessDenied(err) => write!(f, "Authorization request was denied: {}", err),
+            PollError::ExpiredToken => write!(f, "Device code expired before authorization"),
+            PollError::Timeout => write!(f, "Timeout while polling for token"),
+            PollError::Cancelled => write!(f, "Polling cancelled by the user"),
+            PollError::Server(err) => write!(f, "Server returned error response: {}", err),
+            PollError::Request(_) => write!(f, "Token request failed"),
+        }
+    }
+}
+
+impl Error for PollError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PollError::Request(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Shared flag that stops a poller running on another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Polling schedule of one device authorization (RFC 8628, section 3.4).
+#[derive(Debug)]
+pub struct Poller {
+    interval: Duration,
+    deadline: Instant,
+    // Whether `deadline` is the configured timeout rather than `expires_in`
+    timeout_first: bool,
+    cancel: CancelFlag,
+    status: Option<Sender<Duration>>,
+    status_interval: Duration,
+    last_status: Instant,
+}
+
+impl Poller {
+    /// Polls until the device code's `expires_in`, or the configured `timeout`
+    /// when that is shorter.
+    pub fn new(details: &StandardDeviceAuthorizationResponse, timeout: Option<Duration>) -> Self {
+        let expires_in = details.expires_in();
+        let timeout_first = timeout.is_some_and(|t| t < expires_in);
+        let limit = if timeout_first { timeout.unwrap_or(expires_in) } else { expires_in };
+        Self {
+            interval: details.interval().min(MAX_DELAY),
+            deadline: Instant::now() + limit.min(MAX_DELAY),
+            timeout_first,
+            cancel: CancelFlag::new(),
+            status: None,
+            status_interval: Duration::ZERO,
+            last_status: Instant::now(),
+        }
+    }
+
+    /// Stops polling as soon as `cancel` is set.
+    pub fn with_cancel(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Sends the time left to `status` after a poll that returned no token,
+    /// at most once per `interval`. A zero interval sends nothing.
+    pub fn with_status(mut self, status: Sender<Duration>, interval: Duration) -> Self {
+        self.status = Some(status);
+        self.status_interval = interval;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Reports the time left, unless the last report is too recent.
+    pub fn report(&mut self) {
+        let Some(status) = &self.status else { return };
+        if self.status_interval.is_zero() || self.last_status.elapsed() < self.status_interval {
+            return;
+        }
+        self.last_status = Instant::now();
+        // Nobody listening is not a reason to stop polling
+        let _ = status.send(self.remaining());
+    }
+
+    /// Error to return once polling was cancelled or the deadline has passed.
+    pub fn stopped(&self) -> Option<PollError> {
+        if self.cancel.is_cancelled() {
+            return Some(PollError::Cancelled);
+        }
+        if Instant::now() < self.deadline {
+            return None;
+        }
+        Some(if self.timeout_first {
+            PollError::Timeout
+        } else {
+            PollError::ExpiredToken
+        })
+    }
+
+    /// Handles an error response of the token endpoint and returns how long
+    /// to wait before the next poll, or the error that ends polling.
+    pub fn on_error(
+        &mut self,
+        err: DeviceCodeErrorResponse,
+        retry_after: Option<Duration>,
+    ) -> Result<Duration, PollError> {
+        match err.error() {
+            DeviceCodeErrorResponseType::AuthorizationPending => {}
+            DeviceCodeErrorResponseType::SlowDown => {
+                self.interval = (self.interval + SLOW_DOWN_INCREMENT).min(MAX_DELAY);
+                log::debug!("Server asked to slow down, polling every {:?}", self.interval);
+            }
+            DeviceCodeErrorResponseType::AccessDenied => return Err(PollError::AccessDenied(err)),
+            DeviceCodeErrorResponseType::ExpiredToken => return Err(PollError::ExpiredToken),
+            DeviceCodeErrorResponseType::Basic(_) => return Err(PollError::Server(err)),
+        }
+        Ok(self.wait(retry_after))
+    }
+
+    /// Delay before the next poll: at least the interval, longer when the
+    /// server sent `Retry-After`, plus up to 10% jitter so hosts that started
+    /// together do not poll in lockstep. Never longer than the time left.
+    pub fn wait(&self, retry_after: Option<Duration>) -> Duration {
+        let wait = retry_after
+            .map_or(self.interval, |r| r.max(self.interval))
+            .min(self.remaining());
+        let mut random = [0u8; 2];
+        if SystemRandom::new().fill(&mut random).is_err() {
+            return wait;
+        }
+        let jitter = (wait / 10)
+            .checked_mul(u32::from(u16::from_le_bytes(random)))
+            .map_or(Duration::ZERO, |j| j / u32::from(u16::MAX));
+        wait.saturating_add(jitter)
+    }
+
+    /// Sleeps for `wait`, but never past the deadline, and wakes up early
+    /// when cancelled.
+    pub fn sleep(&self, wait: Duration) {
+        let until = Instant::now()
+            .checked_add(wait)
+            .map_or(self.deadline, |until| until.min(self.deadline));
+        while !self.cancel.is_cancelled() {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            std::thread::sleep(remaining.min(CANCEL_CHECK_INTERVAL));
+        }
+    }
+}
+
+/// `Retry-After` in either of its forms, delay-seconds or HTTP-date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs).min(MAX_DELAY));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+        .map(|delay| delay.min(MAX_DELAY))
+}
@@ -0,0 +1,144 @@
+use std::fs::{self, DirBuilder, File, Metadata, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// A directory of files that only the module's user, normally root, may own
+/// or access. Anything else found there is refused rather than trusted.
+#[derive(Debug, Clone)]
+pub(crate) struct PrivateDir {
+    dir: PathBuf,
+    owner: u32,
+}
+
+impl PrivateDir {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            owner: users::get_effective_uid(),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Contents of `name`, or `None` if it does not exist.
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.check_dir()?;
+        let path = self.path(name);
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+        };
+        self.check(&path, &meta, false)?;
+        let mut file = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // The file checked above must be the one opened
+        let opened = file.metadata()?;
+        if (opened.dev(), opened.ino()) != (meta.dev(), meta.ino()) {
+            bail!("{} was replaced while being read", path.display());
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    }
+
+    /// Replaces `name` atomically with a file of mode `0600`.
+    pub fn write(&self, name: &str, contents: &[u8]) -> Result<()> {
+        self.check_dir()?;
+        let path = self.path(name);
+        let tmp = self.path(&format!(".{}.{}", name, std::process::id()));
+        let _ = fs::remove_file(&tmp);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let path = self.path(name);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Names of the files in the directory, leaving out hidden ones such as
+    /// secrets and temporary files.
+    pub fn names(&self) -> Result<Vec<String>> {
+        self.check_dir()?;
+        let mut names = Vec::new();
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to list {}", self.dir.display()))?;
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// A random key of `N` bytes stored as `name`, created on first use.
+    pub fn secret<const N: usize>(&self, name: &str) -> Result<[u8; N]> {
+        let to_key = |contents: Vec<u8>| -> Result<[u8; N]> {
+            contents
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("{} has the wrong length", self.path(name).display()))
+        };
+        if let Some(contents) = self.read(name)? {
+            return to_key(contents);
+        }
+        let mut secret = [0u8; N];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| anyhow::anyhow!("Failed to generate {}", name))?;
+        let path = self.path(name);
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(mut file) => {
+                file.write_all(&secret)?;
+                file.sync_all()?;
+                Ok(secret)
+            }
+            // Created by a concurrent login
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                to_key(self.read(name)?.with_context(|| format!("{} disappeared", path.display()))?)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        }
+    }
+
+    fn check_dir(&self) -> Result<()> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let meta = fs::symlink_metadata(&self.dir)?;
+        self.check(&self.dir, &meta, true)
+    }
+
+    fn check(&self, path: &Path, meta: &Metadata, dir: bool) -> Result<()> {
+        let kind_ok = if dir { meta.is_dir() } else { meta.is_file() };
+        if !kind_ok || meta.uid() != self.owner || meta.mode() & 0o077 != 0 {
+            bail!(
+                "{} must be a {} owned by uid {} without group or other access",
+                path.display(),
+                if dir { "directory" } else { "file" },
+                self.owner
+            );
+        }
+        Ok(())
+    }
+}
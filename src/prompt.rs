@@ -32,6 +32,7 @@ pub struct UserPrompt {
     verification_uri: String,
     user_code: UserCode,
     messages: Messages,
+    auto_poll: bool,
 }
 
 impl UserPrompt {
@@ -45,6 +46,21 @@ impl UserPrompt {
             verification_uri: device_code_resp.verification_uri().to_string(),
             user_code: device_code_resp.user_code().to_owned(),
             messages: messages.clone(),
+            auto_poll: false,
+        }
+    }
+
+    /// Ends the prompt with `prompt_auto_poll`, which tells the user that
+    /// polling already runs and how to cancel it.
+    pub fn set_auto_poll(&mut self, auto_poll: bool) {
+        self.auto_poll = auto_poll;
+    }
+
+    fn closing(&self) -> &str {
+        if self.auto_poll {
+            &self.messages.prompt_auto_poll
+        } else {
+            &self.messages.prompt_enter
         }
     }
 
@@ -52,7 +68,7 @@ impl UserPrompt {
         let qrcode: Option<QrString>;
 
         if let Some(verification_uri_complete) = &self.verification_uri_complete {
-            qrcode = match qr_code(&verification_uri_complete.secret()) {
+            qrcode = match qr_code(verification_uri_complete.secret()) {
                 Err(e) => {
                     log::warn!("Failed to create QR code: {e}");
                     None
@@ -81,14 +97,14 @@ impl Display for UserPrompt {
                 qr.secret(),
                 &self.messages.prompt_complete,
                 url.secret(),
-                &self.messages.prompt_enter
+                self.closing()
             ),
             (None, Some(url)) => write!(
                 f,
                 "\n{}\n{}\n{}",
                 &self.messages.prompt_no_qr_complete,
                 url.secret(),
-                &self.messages.prompt_enter
+                self.closing()
             ),
             (Some(qr), None) => write!(
                 f,
@@ -98,7 +114,7 @@ impl Display for UserPrompt {
                 self.verification_uri,
                 &self.messages.prompt_code,
                 self.user_code.secret(),
-                &self.messages.prompt_enter
+                self.closing()
             ),
             (None, None) => write!(
                 f,
@@ -107,13 +123,13 @@ impl Display for UserPrompt {
                 self.verification_uri,
                 &self.messages.prompt_code,
                 self.user_code.secret(),
-                &self.messages.prompt_enter
+                self.closing()
             ),
         }
     }
 }
 pub fn qr_code(url: &String) -> Result<String, Box<dyn std::error::Error>> {
-    let qr = QrCode::new(&url)?;
+    let qr = QrCode::new(url)?;
 
     let qr_text = qr
         .render::<unicode::Dense1x2>()
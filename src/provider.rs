@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::Config;
+
+const AZURE_LOGIN_HOST: &str = "login.microsoftonline.com";
+const AZURE_DEFAULT_TENANT: &str = "common";
+
+/// Identity provider flavour. It only supplies defaults for the endpoints and
+/// issuers that are not set explicitly in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Generic,
+    Azure,
+}
+
+impl Provider {
+    // Configs written before `provider` existed are all Azure ones, so keep
+    // treating them that way when they point at the Microsoft login host.
+    pub fn detect(config: &Config) -> Self {
+        let azure_host = config
+            .oauth_token_url
+            .as_ref()
+            .is_some_and(|url| url.host_str() == Some(AZURE_LOGIN_HOST));
+        if config.tenant_id.is_some() || azure_host {
+            Provider::Azure
+        } else {
+            Provider::Generic
+        }
+    }
+
+    pub fn default_jwks_url(&self, config: &Config) -> Option<Url> {
+        match self {
+            Provider::Generic => None,
+            Provider::Azure => Url::parse(&format!(
+                "https://{}/{}/discovery/v2.0/keys",
+                AZURE_LOGIN_HOST,
+                azure_tenant(config)
+            ))
+            .ok(),
+        }
+    }
+
+    pub fn default_issuers(&self, config: &Config) -> Vec<String> {
+        match self {
+            Provider::Generic => Vec::new(),
+            Provider::Azure => {
+                let tenant_id = azure_tenant(config);
+                vec![
+                    format!("https://{}/{}/v2.0", AZURE_LOGIN_HOST, tenant_id),
+                    format!("https://sts.windows.net/{}/", tenant_id),
+                ]
+            }
+        }
+    }
+}
+
+fn azure_tenant(config: &Config) -> &str {
+    config.tenant_id.as_deref().unwrap_or(AZURE_DEFAULT_TENANT)
+}
@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::private_dir::PrivateDir;
+
+const HOST_KEY: &str = ".host_key";
+
+/// The file stored per local user and service, version 1. `user`, `service`
+/// and `created_at` are also bound to the ciphertext, they are only kept in
+/// the clear to prune old entries.
+#[derive(Serialize, Deserialize, Debug)]
+struct Sealed {
+    version: u32,
+    user: String,
+    service: String,
+    created_at: i64,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A stored refresh token and the subject it was issued to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredToken {
+    pub refresh_token: String,
+    pub sub: Option<String>,
+}
+
+/// Refresh tokens kept for silent reauthentication, one per local user and
+/// service, encrypted with AES-256-GCM under a host key in the same private
+/// directory. The ciphertext is bound to the user, the service and the
+/// client, so a file copied to another name does not decrypt. Tokens older
+/// than `max_age` are dropped.
+#[derive(Debug)]
+pub struct RefreshTokenStore {
+    dir: PrivateDir,
+    client_id: String,
+    max_age: Duration,
+}
+
+impl RefreshTokenStore {
+    pub fn new(dir: impl Into<PathBuf>, client_id: &str, max_age: Duration) -> Self {
+        Self {
+            dir: PrivateDir::new(dir),
+            client_id: client_id.to_string(),
+            max_age,
+        }
+    }
+
+    /// Stores `token` for `user` and `service`. Entries that are too old are
+    /// removed on the way. `now` is in seconds since the epoch.
+    pub fn save(&self, user: &str, service: &str, token: &StoredToken, now: i64) -> Result<()> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("Failed to generate nonce"))?;
+        let mut data = serde_json::to_vec(token)?;
+        self.key()?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(self.aad(user, service, now)),
+                &mut data,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt refresh token"))?;
+        let sealed = Sealed {
+            version: 1,
+            user: user.to_string(),
+            service: service.to_string(),
+            created_at: now,
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(data),
+        };
+        self.dir.write(&file_name(user, service), &serde_json::to_vec(&sealed)?)?;
+        self.prune(now);
+        Ok(())
+    }
+
+    /// The token stored for `user` and `service`, unless it is too old.
+    pub fn load(&self, user: &str, service: &str, now: i64) -> Result<Option<StoredToken>> {
+        let Some(contents) = self.dir.read(&file_name(user, service))? else {
+            return Ok(None);
+        };
+        let sealed: Sealed = serde_json::from_slice(&contents)?;
+        if sealed.version != 1 {
+            bail!("Unsupported refresh token file version {}", sealed.version);
+        }
+        let nonce: [u8; NONCE_LEN] = STANDARD
+            .decode(&sealed.nonce)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid nonce"))?;
+        let mut data = STANDARD.decode(&sealed.ciphertext)?;
+        let token = self
+            .key()?
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(self.aad(user, service, sealed.created_at)),
+                &mut data,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt refresh token of {}", user))?;
+        if !self.is_fresh(sealed.created_at, now) {
+            log::info!("Stored refresh token of {} for {} is too old", user, service);
+            self.remove(user, service)?;
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(token)?))
+    }
+
+    pub fn remove(&self, user: &str, service: &str) -> Result<()> {
+        self.dir.remove(&file_name(user, service))
+    }
+
+    /// Removes the entries that are too old, and any that cannot be read.
+    fn prune(&self, now: i64) {
+        let names = match self.dir.names() {
+            Ok(names) => names,
+            Err(e) => {
+                log::warn!("Failed to prune refresh tokens: {:#}", e);
+                return;
+            }
+        };
+        for name in names {
+            let sealed = self
+                .dir
+                .read(&name)
+                .ok()
+                .flatten()
+                .and_then(|contents| serde_json::from_slice::<Sealed>(&contents).ok());
+            if !sealed.is_some_and(|sealed| self.is_fresh(sealed.created_at, now)) {
+                log::debug!("Removing refresh token {}", self.dir.path(&name).display());
+                let _ = self.dir.remove(&name);
+            }
+        }
+    }
+
+    // A clock set back must not stretch the lifetime either
+    fn is_fresh(&self, created_at: i64, now: i64) -> bool {
+        created_at <= now && now - created_at < self.max_age.as_secs() as i64
+    }
+
+    fn aad(&self, user: &str, service: &str, created_at: i64) -> Vec<u8> {
+        format!("{}\0{}\0{}\0{}", self.client_id, user, service, created_at).into_bytes()
+    }
+
+    fn key(&self) -> Result<LessSafeKey> {
+        let key: [u8; 32] = self.dir.secret(HOST_KEY)?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| anyhow::anyhow!("Invalid host key"))?;
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+// Any user or service name maps to a safe file name
+fn file_name(user: &str, service: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, format!("{}\0{}", user, service).as_bytes());
+    let hex: String = hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.json", hex)
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::claims::VerifiedClaims;
+
+/// Claims exported into the PAM environment when a session opens, so that
+/// the session can tell which identity opened it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionEnv {
+    /// Claim names or JSON pointers to export. `groups` includes groups
+    /// resolved through Microsoft Graph.
+    #[serde(default)]
+    pub claims: Vec<String>,
+
+    /// Prepended to the upper-cased claim name to form the variable name.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    /// Variable names for individual claims, e.g. `"exp": "OAUTH2_TOKEN_EXP"`.
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+}
+
+impl Default for SessionEnv {
+    fn default() -> Self {
+        Self {
+            claims: Vec::new(),
+            prefix: default_prefix(),
+            names: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_prefix() -> String {
+    "OAUTH2_".to_string()
+}
+
+impl SessionEnv {
+    /// `NAME=value` pairs for the configured claims present in `claims`.
+    /// Values lose control characters and lists are joined with commas, so a
+    /// claim cannot add variables or lines of its own.
+    pub fn exports(&self, claims: &VerifiedClaims) -> Vec<(String, String)> {
+        let mut exports = Vec::new();
+        for claim in &self.claims {
+            let name = self.variable(claim);
+            if !is_variable_name(&name) {
+                log::warn!("Not exporting claim '{}': invalid variable name '{}'", claim, name);
+                continue;
+            }
+            let value = if claim == "groups" {
+                claims.groups().map(|groups| groups.join(","))
+            } else {
+                claims.lookup(claim).and_then(to_value)
+            };
+            match value {
+                Some(value) => exports.push((name, sanitize(&value))),
+                None => log::debug!("Not exporting claim '{}': missing or not a value", claim),
+            }
+        }
+        exports
+    }
+
+    fn variable(&self, claim: &str) -> String {
+        if let Some(name) = self.names.get(claim) {
+            return name.clone();
+        }
+        let suffix: String = claim
+            .trim_start_matches('/')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        format!("{}{}", self.prefix, suffix)
+    }
+}
+
+fn to_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        Value::Array(values) => {
+            let values: Vec<String> = values
+                .iter()
+                .filter(|v| !v.is_array() && !v.is_object())
+                .filter_map(to_value)
+                .collect();
+            Some(values.join(","))
+        }
+        _ => None,
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
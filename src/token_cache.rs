@@ -0,0 +1,184 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::{fchown, MetadataExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{openat, renameat, OFlag};
+use nix::sys::stat::{mkdirat, Mode};
+use nix::unistd::{unlinkat, UnlinkatFlags};
+use oauth2::TokenResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::claims::VerifiedClaims;
+use crate::config::Config;
+use crate::oauth_device::DeviceTokenResponse;
+
+/// Key under which `sm_authenticate` leaves the `CachedTokens` for
+/// `sm_setcred` and `sm_close_session`.
+pub const TOKENS_KEY: &str = "pam_oauth2_device_tokens";
+
+/// Contents of the token cache file, version 1. Fields that the provider
+/// did not return are left out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedTokens {
+    pub version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    pub client_id: String,
+    /// Where tools can use `refresh_token`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_endpoint: Option<String>,
+    /// Remote username the tokens were issued to.
+    pub username: String,
+    pub token_type: String,
+    pub access_token: String,
+    /// Expiry of `access_token` in seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Whether `refresh_token` was kept for silent reauthentication, so
+    /// `revoke_on_close` must leave it alone. Not part of the file.
+    #[serde(skip)]
+    pub refresh_token_stored: bool,
+}
+
+impl CachedTokens {
+    /// `now` is in seconds since the epoch.
+    pub fn new(token: &DeviceTokenResponse, claims: &VerifiedClaims, config: &Config, now: i64) -> Self {
+        Self {
+            version: 1,
+            issuer: claims.get_str("iss").map(str::to_string),
+            client_id: config.client_id.clone(),
+            token_endpoint: config.oauth_token_url.as_ref().map(|url| url.to_string()),
+            username: claims.username().to_string(),
+            token_type: "Bearer".to_string(),
+            access_token: token.access_token().secret().clone(),
+            expires_at: token.expires_in().map(|d| now + d.as_secs() as i64),
+            refresh_token: token.refresh_token().map(|t| t.secret().clone()),
+            id_token: token.extra_fields().id_token.clone(),
+            scope: token.scopes().map(|scopes| {
+                scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" ")
+            }),
+            refresh_token_stored: false,
+        }
+    }
+}
+
+/// A user's token cache file, readable only by that user.
+#[derive(Debug)]
+pub struct TokenCache {
+    path: PathBuf,
+    uid: u32,
+    gid: u32,
+}
+
+impl TokenCache {
+    /// `template` may contain `{uid}` and `{user}`.
+    pub fn new(template: &str, user: &str, uid: u32, gid: u32) -> Result<Self> {
+        if user.contains('/') || user.starts_with('.') {
+            bail!("Invalid username '{}' for the token cache", user);
+        }
+        let path = template
+            .replace("{uid}", &uid.to_string())
+            .replace("{user}", user);
+        Ok(Self {
+            path: PathBuf::from(path),
+            uid,
+            gid,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the cache atomically. Only the last directory of the path is
+    /// created, its parent, such as `/run/user/$UID`, must already exist.
+    pub fn write(&self, tokens: &CachedTokens) -> Result<()> {
+        let dir = self.open_dir(true)?.context("Token cache directory disappeared")?;
+        let file_name = self.file_name()?;
+        let tmp = format!(".{}.{}", file_name.to_string_lossy(), std::process::id());
+        let _ = unlinkat(Some(dir.as_raw_fd()), tmp.as_str(), UnlinkatFlags::NoRemoveDir);
+        // The user owns the directory, so every step goes through its descriptor
+        let fd = openat(
+            dir.as_raw_fd(),
+            tmp.as_str(),
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::from_bits_truncate(0o600),
+        )
+        .with_context(|| format!("Failed to create {}", tmp))?;
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        fchown(&file, Some(self.uid), Some(self.gid))
+            .with_context(|| format!("Failed to change owner of {}", tmp))?;
+        serde_json::to_writer_pretty(&mut file, tokens)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        renameat(Some(dir.as_raw_fd()), tmp.as_str(), Some(dir.as_raw_fd()), file_name)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        let Some(dir) = self.open_dir(false)? else {
+            return Ok(());
+        };
+        match unlinkat(Some(dir.as_raw_fd()), self.file_name()?, UnlinkatFlags::NoRemoveDir) {
+            Err(e) if e != Errno::ENOENT => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file_name(&self) -> Result<&OsStr> {
+        self.path.file_name().context("Token cache path has no file name")
+    }
+
+    /// Opens the last directory of the path without following symlinks and
+    /// checks that the user owns it. Returns `None` if it does not exist and
+    /// `create` is false.
+    fn open_dir(&self, create: bool) -> Result<Option<File>> {
+        let dir = self.path.parent().context("Token cache path has no directory")?;
+        let parent = dir.parent().context("Token cache directory has no parent")?;
+        let name = dir.file_name().context("Token cache directory has no name")?;
+        let parent = File::open(parent)
+            .with_context(|| format!("Failed to open {}", parent.display()))?;
+
+        let created = create
+            && match mkdirat(parent.as_raw_fd(), name, Mode::from_bits_truncate(0o700)) {
+                Ok(()) => true,
+                Err(Errno::EEXIST) => false,
+                Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+            };
+        let fd = match openat(
+            parent.as_raw_fd(),
+            name,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        ) {
+            Ok(fd) => fd,
+            Err(Errno::ENOENT) if !create => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("{} is not a directory", dir.display()))
+            }
+        };
+        let file = unsafe { File::from_raw_fd(fd) };
+        if created {
+            fchown(&file, Some(self.uid), Some(self.gid))
+                .with_context(|| format!("Failed to change owner of {}", dir.display()))?;
+        }
+        let meta = file.metadata()?;
+        if !meta.is_dir() || meta.uid() != self.uid {
+            bail!("{} is not a directory owned by uid {}", dir.display(), self.uid);
+        }
+        Ok(Some(file))
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// One step of the `username_transforms` pipeline, applied in the configured order.
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UsernameTransform {
+    /// `jdoe@corp.example.com` -> `jdoe`
+    StripDomain,
+    Lowercase,
+    RegexReplace {
+        #[serde_as(as = "serde_with::DisplayFromStr")]
+        pattern: Regex,
+        #[serde(default)]
+        replacement: String,
+    },
+    /// Rejects the username unless every character matches the regex character
+    /// class `allowed`, e.g. `a-z0-9._-`.
+    AllowedCharacters {
+        #[serde_as(as = "serde_with::DisplayFromStr")]
+        allowed: CharacterClass,
+    },
+}
+
+/// A regex character class such as `a-z0-9._-`, compiled when the config is
+/// read so that an invalid one is a config error.
+#[derive(Debug, Clone)]
+pub struct CharacterClass {
+    class: String,
+    allowlist: Regex,
+}
+
+impl FromStr for CharacterClass {
+    type Err = regex::Error;
+
+    fn from_str(class: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            class: class.to_string(),
+            allowlist: Regex::new(&format!("^[{}]*$", class))?,
+        })
+    }
+}
+
+impl fmt::Display for CharacterClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.class)
+    }
+}
+
+impl UsernameTransform {
+    fn apply(&self, username: String) -> Result<String> {
+        let username = match self {
+            UsernameTransform::StripDomain => match username.split_once('@') {
+                Some((local, _)) => local.to_string(),
+                None => username,
+            },
+            UsernameTransform::Lowercase => username.to_lowercase(),
+            UsernameTransform::RegexReplace {
+                pattern,
+                replacement,
+            } => pattern.replace_all(&username, replacement.as_str()).into_owned(),
+            UsernameTransform::AllowedCharacters { allowed } => {
+                if !allowed.allowlist.is_match(&username) {
+                    bail!(
+                        "Username '{}' contains characters outside of [{}]",
+                        username,
+                        allowed
+                    );
+                }
+                username
+            }
+        };
+        Ok(username)
+    }
+}
+
+/// Runs `remote_username` through `transforms`. An empty result is an error
+/// rather than a silent fallback.
+pub fn transform(remote_username: &str, transforms: &[UsernameTransform]) -> Result<String> {
+    let mut username = remote_username.to_string();
+    for t in transforms {
+        username = t.apply(username)?;
+    }
+
+    if username.is_empty() {
+        bail!("Username derived from '{}' is empty", remote_username);
+    }
+    if username != remote_username {
+        log::debug!("Username transformed: {} -> {}", remote_username, username);
+    }
+    Ok(username)
+}
@@ -0,0 +1,156 @@
+mod test_logger;
+mod utils;
+
+use pam_oauth2_device::access_rules::{AccessRules, Action, LoginContext};
+use pam_oauth2_device::authorization::AuthorizationRules;
+use pam_oauth2_device::claims::VerifiedClaims;
+use serde_json::json;
+use utils::{mock_config, verified_claims, Mock};
+
+use test_logger::LOGGER;
+
+const ACCESS_RULES: &str = r#"{
+    "default": "allow",
+    "rules": [
+        { "name": "db-dba-ssh", "services": ["sshd"], "hosts": ["db-*.prod.example.com"], "groups": ["dba"], "action": "allow" },
+        { "name": "db-sre-sudo", "services": ["sudo"], "hosts": ["db-*.prod.example.com"], "groups": ["sre"], "action": "allow" },
+        { "name": "db-other", "hosts": ["db-*.prod.example.com"], "action": "deny" },
+        { "name": "office-only", "users": ["svc-*"], "rhosts": ["10.20.0.0/16", "192.0.2.7"], "action": "allow" },
+        { "name": "svc-elsewhere", "users": ["svc-*"], "action": "ignore" }
+    ]
+}"#;
+
+fn rules(name: &str, contents: &str) -> anyhow::Result<AccessRules> {
+    let path = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-access-rules-{}-{}.json",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    AccessRules::load(&path)
+}
+
+fn member_of(groups: &[&str]) -> VerifiedClaims {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = mock_config(&mock.server.url(), None);
+    let claims = json!({ "preferred_username": "alice", "sub": "alice-sub", "groups": groups });
+    verified_claims(&config, claims).unwrap()
+}
+
+fn login(service: &str, hostname: &str, rhost: Option<&str>) -> LoginContext {
+    LoginContext {
+        service: Some(service.to_string()),
+        hostname: Some(hostname.to_string()),
+        rhost: rhost.map(str::to_string),
+    }
+}
+
+#[test]
+fn service_and_host() {
+    let _logger = LOGGER.lock().unwrap();
+    let rules = rules("service", ACCESS_RULES).unwrap();
+    let db = "db-01.prod.example.com";
+
+    let dba = member_of(&["dba"]);
+    assert_eq!(rules.evaluate(&dba, "alice", &login("sshd", db, None)), Action::Allow);
+    assert_eq!(rules.evaluate(&dba, "alice", &login("sudo", db, None)), Action::Deny);
+
+    let sre = member_of(&["sre"]);
+    assert_eq!(rules.evaluate(&sre, "alice", &login("sshd", db, None)), Action::Deny);
+    assert_eq!(rules.evaluate(&sre, "alice", &login("sudo", db, None)), Action::Allow);
+
+    // Hostnames are not case-sensitive
+    assert_eq!(
+        rules.evaluate(&sre, "alice", &login("sudo", "DB-01.prod.example.com", None)),
+        Action::Allow
+    );
+    assert_eq!(
+        rules.evaluate(&sre, "alice", &login("sshd", "web-01.prod.example.com", None)),
+        Action::Allow
+    );
+}
+
+#[test]
+fn rhost_networks() {
+    let _logger = LOGGER.lock().unwrap();
+    let rules = rules("rhost", ACCESS_RULES).unwrap();
+    let claims = member_of(&[]);
+    let web = "web-01";
+
+    for (rhost, action) in [
+        (Some("10.20.3.4"), Action::Allow),
+        (Some("192.0.2.7"), Action::Allow),
+        (Some("192.0.2.8"), Action::Ignore),
+        (Some("office.example.com"), Action::Ignore),
+        (None, Action::Ignore),
+    ] {
+        assert_eq!(
+            rules.evaluate(&claims, "svc-backup", &login("sshd", web, rhost)),
+            action,
+            "{:?}",
+            rhost
+        );
+    }
+}
+
+#[test]
+fn deny_by_default() {
+    let _logger = LOGGER.lock().unwrap();
+    let rules = rules(
+        "default",
+        r#"{ "rules": [{ "name": "sudo", "services": ["sudo"], "action": "allow" }] }"#,
+    )
+    .unwrap();
+    let claims = member_of(&[]);
+
+    assert_eq!(rules.evaluate(&claims, "alice", &login("sudo", "h", None)), Action::Allow);
+    assert_eq!(rules.evaluate(&claims, "alice", &login("sshd", "h", None)), Action::Deny);
+}
+
+#[test]
+fn log_names_deciding_rule() {
+    let logger = LOGGER.lock().unwrap();
+    let rules = rules("log", ACCESS_RULES).unwrap();
+    let claims = member_of(&["sre"]);
+
+    rules.evaluate(&claims, "alice", &login("sshd", "db-01.prod.example.com", Some("10.1.1.1")));
+    assert_eq!(
+        logger.msg(),
+        "Access rule 'db-other': alice (sub alice-sub) -> alice denied (service sshd, host db-01.prod.example.com, rhost 10.1.1.1)"
+    );
+
+    rules.evaluate(&claims, "bob", &login("login", "web-01", None));
+    assert_eq!(
+        logger.msg(),
+        "Access rules: alice (sub alice-sub) -> bob allowed by default, no rule matched (service login, host web-01, rhost -)"
+    );
+}
+
+#[test]
+fn invalid_network() {
+    let err = rules(
+        "invalid",
+        r#"{ "rules": [{ "name": "bad", "rhosts": ["10.0.0.0/33"], "action": "allow" }] }"#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid rhost '10.0.0.0/33' in rule 'bad'"
+    );
+}
+
+#[test]
+fn friendly_group_names() {
+    const DBA_ID: &str = "a2b4c6d8-0000-4000-8000-000000000001";
+    let authorization: AuthorizationRules =
+        serde_json::from_value(json!({ "names": { "dba": DBA_ID } })).unwrap();
+    let plain = rules("names", ACCESS_RULES).unwrap();
+    let named = plain.clone().with_names(&authorization);
+    let db = "db-01.prod.example.com";
+
+    let dba = member_of(&[DBA_ID]);
+    assert_eq!(named.evaluate(&dba, "alice", &login("sshd", db, None)), Action::Allow);
+    // Without the names only the literal value matches
+    assert_eq!(plain.evaluate(&dba, "alice", &login("sshd", db, None)), Action::Deny);
+}
@@ -0,0 +1,135 @@
+mod test_logger;
+mod utils;
+
+use pam_oauth2_device::claims::VerifiedClaims;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+use utils::{verified_claims, Mock};
+
+use test_logger::LOGGER;
+
+const ACCOUNT_MAP: &str = r#"{
+    "accounts": {
+        "deploy": { "usernames": ["alice", "bob"], "groups": ["deployers"] },
+        "jdoe": { "subjects": ["0f1e2d3c"] },
+        "svc-*": { "groups": ["ops-*"] }
+    }
+}"#;
+
+/// Client whose `account_map` file contains `map`, and the mock serving its keys.
+fn mapped_client(name: &str, map: &str) -> (Mock, OAuthClient) {
+    let path = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-account-map-{}-{}.json",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, map).unwrap();
+
+    let (mut mock, oauth_client) =
+        Mock::builder().init_with(None, |config| config.account_map = Some(path));
+    mock.http_jwks();
+    (mock, oauth_client)
+}
+
+fn verified(oauth_client: &OAuthClient, username: &str, extra: Value) -> VerifiedClaims {
+    let mut claims = json!({ "preferred_username": username, "sub": format!("{}-sub", username) });
+    claims.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    verified_claims(oauth_client.config(), claims).unwrap()
+}
+
+#[test]
+fn shared_account() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("shared", ACCOUNT_MAP);
+
+    let alice = verified(&oauth_client, "alice", json!({}));
+    let carol = verified(&oauth_client, "carol", json!({ "groups": ["deployers"] }));
+    let mallory = verified(&oauth_client, "mallory", json!({ "groups": ["users"] }));
+
+    assert!(oauth_client.authorize(&alice, "deploy"));
+    assert!(oauth_client.authorize(&carol, "deploy"));
+    assert!(!oauth_client.authorize(&mallory, "deploy"));
+}
+
+#[test]
+fn one_to_one_by_subject() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("one_to_one", ACCOUNT_MAP);
+
+    let john = verified(&oauth_client, "john.doe", json!({ "sub": "0f1e2d3c" }));
+    let impostor = verified(&oauth_client, "jdoe", json!({ "sub": "ffffffff" }));
+
+    assert!(oauth_client.authorize(&john, "jdoe"));
+    // A mapped account no longer accepts its own name
+    assert!(!oauth_client.authorize(&impostor, "jdoe"));
+}
+
+#[test]
+fn wildcard_entries() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("wildcard", ACCOUNT_MAP);
+
+    let ops = verified(&oauth_client, "carol", json!({ "groups": ["ops-eu"] }));
+    let dev = verified(&oauth_client, "dave", json!({ "groups": ["dev"] }));
+
+    assert!(oauth_client.authorize(&ops, "svc-backup"));
+    assert!(!oauth_client.authorize(&dev, "svc-backup"));
+    assert!(!oauth_client.authorize(&ops, "svc"));
+}
+
+#[test]
+fn unmapped_accounts() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("same_name", ACCOUNT_MAP);
+    let carol = verified(&oauth_client, "carol", json!({}));
+
+    assert!(oauth_client.authorize(&carol, "carol"));
+    assert!(!oauth_client.authorize(&carol, "dave"));
+
+    let (_mock, oauth_client) = mapped_client(
+        "deny",
+        r#"{ "unmapped": "deny", "accounts": { "deploy": { "usernames": ["*"] } } }"#,
+    );
+    let carol = verified(&oauth_client, "carol", json!({}));
+
+    assert!(oauth_client.authorize(&carol, "deploy"));
+    assert!(!oauth_client.authorize(&carol, "carol"));
+}
+
+#[test]
+fn root_is_never_mapped() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("root", r#"{ "accounts": { "*": { "usernames": ["*"] } } }"#);
+    let alice = verified(&oauth_client, "alice", json!({}));
+
+    assert!(oauth_client.authorize(&alice, "anything"));
+    assert!(!oauth_client.authorize(&alice, "root"));
+}
+
+#[test]
+fn decision_is_logged() {
+    let logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("logged", ACCOUNT_MAP);
+    let bob = verified(&oauth_client, "bob", json!({}));
+
+    assert!(oauth_client.authorize(&bob, "deploy"));
+    assert_eq!(
+        logger.msg(),
+        "Account map: bob (sub bob-sub) -> deploy allowed by entry 'deploy' (username bob matches 'bob')"
+    );
+
+    assert!(!oauth_client.authorize(&bob, "svc-web"));
+    assert_eq!(
+        logger.msg(),
+        "Account map: bob (sub bob-sub) -> svc-web denied, no match in entries [\"svc-*\"]"
+    );
+}
+
+#[test]
+fn unreadable_map_denies() {
+    let _logger = LOGGER.lock().unwrap();
+    let (_mock, oauth_client) = mapped_client("broken", "{ not json");
+    let alice = verified(&oauth_client, "alice", json!({}));
+
+    assert!(!oauth_client.authorize(&alice, "alice"));
+}
@@ -0,0 +1,67 @@
+mod utils;
+
+use chrono::Utc;
+use pam_oauth2_device::access_rules::Action;
+use pam_oauth2_device::account::{AccountStatus, AuthDecision};
+use serde_json::{json, Value};
+use utils::{mock_config, verified_claims, Mock, MockConfig};
+
+/// A decision on a token with `extra` merged into the default claims.
+fn decide(extra: Value, policy: Action) -> (MockConfig, AuthDecision) {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let mut config = mock_config(&mock.server.url(), None);
+    let claims = verified_claims(&config, extra).unwrap();
+    config.account_expiry_claim = Some("/account/expires".to_string());
+    let decision = AuthDecision {
+        local_user: "test".to_string(),
+        claims,
+        policy,
+    };
+    (config, decision)
+}
+
+#[test]
+fn policy_is_carried_over() {
+    let now = Utc::now().timestamp();
+    for (policy, status) in [
+        (Action::Allow, AccountStatus::Valid),
+        (Action::Deny, AccountStatus::Denied),
+        (Action::Ignore, AccountStatus::Ignored),
+    ] {
+        let (config, decision) = decide(json!({}), policy);
+        assert_eq!(decision.status(&config, now), status);
+    }
+}
+
+#[test]
+fn token_expiry_with_leeway() {
+    let (config, decision) = decide(json!({}), Action::Allow);
+    let exp = decision.claims.exp().unwrap();
+
+    assert_eq!(decision.status(&config, exp + 60), AccountStatus::Valid);
+    assert_eq!(decision.status(&config, exp + 61), AccountStatus::Expired);
+}
+
+#[test]
+fn account_expiry_claim() {
+    let now = Utc::now().timestamp();
+    let (config, decision) = decide(json!({ "account": { "expires": now - 1 } }), Action::Allow);
+    assert_eq!(decision.status(&config, now), AccountStatus::Expired);
+
+    let (config, decision) = decide(json!({ "account": { "expires": now + 600 } }), Action::Allow);
+    assert_eq!(decision.status(&config, now), AccountStatus::Valid);
+}
+
+#[test]
+fn azure_password_expiry() {
+    let now = Utc::now().timestamp();
+    let (config, decision) = decide(
+        json!({ "iat": now - 120, "pwd_exp": "100", "pwd_url": "https://portal.example/ChangePassword.aspx" }),
+        Action::Allow,
+    );
+    assert_eq!(decision.status(&config, now), AccountStatus::PasswordExpired);
+
+    let (config, decision) = decide(json!({ "iat": now - 120, "pwd_exp": 3600 }), Action::Allow);
+    assert_eq!(decision.status(&config, now), AccountStatus::Valid);
+}
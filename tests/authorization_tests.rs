@@ -0,0 +1,129 @@
+mod utils;
+
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+use utils::{verified_claims, Mock};
+
+const GLOBAL_ADMIN: &str = "62e90394-69f5-4237-9190-012177145e10";
+const OPS_GROUP: &str = "a2b4c6d8-0000-4000-8000-000000000001";
+
+fn client_with(authorization: Value, allowed_groups: Option<Vec<&str>>) -> (Mock, OAuthClient) {
+    let (mut mock, oauth_client) = Mock::builder().init_with(None, |config| {
+        config.authorization = serde_json::from_value(authorization).unwrap();
+        config.allowed_groups = allowed_groups.map(|g| g.iter().map(|s| s.to_string()).collect());
+    });
+    mock.http_jwks();
+    (mock, oauth_client)
+}
+
+/// Whether a token with `extra` merged into the default claims may log in.
+fn authorized(oauth_client: &OAuthClient, extra: Value) -> bool {
+    let verified = verified_claims(oauth_client.config(), extra).unwrap();
+    oauth_client.authorize(&verified, "test")
+}
+
+#[test]
+fn app_roles_any_of() {
+    let (_mock, oauth_client) = client_with(
+        json!({ "rules": [{ "claim": "roles", "any_of": ["Linux.Login", "Linux.Admin"] }] }),
+        None,
+    );
+
+    assert!(authorized(&oauth_client, json!({ "roles": ["Reader", "Linux.Login"] })));
+    assert!(!authorized(&oauth_client, json!({ "roles": ["Reader"] })));
+    assert!(!authorized(&oauth_client, json!({})));
+}
+
+#[test]
+fn keycloak_realm_roles_all_of() {
+    let (_mock, oauth_client) = client_with(
+        json!({ "rules": [{ "claim": "/realm_access/roles", "all_of": ["ssh", "admin"] }] }),
+        None,
+    );
+
+    assert!(authorized(
+        &oauth_client,
+        json!({ "realm_access": { "roles": ["ssh", "admin", "offline_access"] } })
+    ));
+    assert!(!authorized(
+        &oauth_client,
+        json!({ "realm_access": { "roles": ["ssh"] } })
+    ));
+}
+
+#[test]
+fn friendly_names() {
+    let (_mock, oauth_client) = client_with(
+        json!({
+            "names": { "global-admin": GLOBAL_ADMIN },
+            "rules": [{ "claim": "wids", "any_of": ["global-admin"] }],
+        }),
+        None,
+    );
+
+    assert!(authorized(&oauth_client, json!({ "wids": [GLOBAL_ADMIN] })));
+    assert!(!authorized(&oauth_client, json!({ "wids": ["global-admin"] })));
+}
+
+#[test]
+fn allowed_groups_by_name() {
+    let (_mock, oauth_client) = client_with(
+        json!({ "names": { "ops": OPS_GROUP } }),
+        Some(vec!["ops"]),
+    );
+
+    assert!(authorized(&oauth_client, json!({ "groups": [OPS_GROUP] })));
+    assert!(!authorized(&oauth_client, json!({ "groups": ["other"] })));
+}
+
+#[test]
+fn require_any_or_all_rules() {
+    let rules = json!([
+        { "claim": "roles", "any_of": ["Linux.Login"] },
+        { "claim": "groups", "any_of": [OPS_GROUP] },
+    ]);
+    let roles_only = json!({ "roles": ["Linux.Login"] });
+    let both = json!({ "roles": ["Linux.Login"], "groups": [OPS_GROUP] });
+
+    let (_mock, oauth_client) = client_with(json!({ "rules": rules }), None);
+    assert!(authorized(&oauth_client, roles_only.clone()));
+
+    let (_mock, oauth_client) = client_with(json!({ "require": "all", "rules": rules }), None);
+    assert!(!authorized(&oauth_client, roles_only));
+    assert!(authorized(&oauth_client, both));
+}
+
+#[test]
+fn rules_and_allowed_groups_both_apply() {
+    let (_mock, oauth_client) = client_with(
+        json!({ "rules": [{ "claim": "roles", "any_of": ["Linux.Login"] }] }),
+        Some(vec![OPS_GROUP]),
+    );
+
+    assert!(!authorized(&oauth_client, json!({ "roles": ["Linux.Login"] })));
+    assert!(!authorized(&oauth_client, json!({ "groups": [OPS_GROUP] })));
+    assert!(authorized(
+        &oauth_client,
+        json!({ "roles": ["Linux.Login"], "groups": [OPS_GROUP] })
+    ));
+}
+
+#[test]
+fn single_value_and_presence() {
+    let (_mock, oauth_client) = client_with(
+        json!({
+            "require": "all",
+            "rules": [
+                { "claim": "tenant_region_scope", "any_of": ["EU"] },
+                { "claim": "/xms_st/sub" },
+            ],
+        }),
+        None,
+    );
+
+    assert!(authorized(
+        &oauth_client,
+        json!({ "tenant_region_scope": "EU", "xms_st": { "sub": "x" } })
+    ));
+    assert!(!authorized(&oauth_client, json!({ "tenant_region_scope": "EU" })));
+}
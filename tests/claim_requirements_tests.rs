@@ -0,0 +1,105 @@
+mod utils;
+
+use chrono::{Duration, Utc};
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+use utils::{verified_claims, Mock};
+
+fn client_requiring(requirements: Value) -> (Mock, OAuthClient) {
+    let (mut mock, oauth_client) = Mock::builder().init_with(None, |config| {
+        config.claim_requirements = serde_json::from_value(requirements).unwrap();
+    });
+    mock.http_jwks();
+    (mock, oauth_client)
+}
+
+/// Validates an id_token with `extra` merged into the default claims and
+/// returns the error message, if any.
+fn validate(oauth_client: &OAuthClient, extra: Value) -> Result<(), String> {
+    verified_claims(oauth_client.config(), extra)
+        .map(|_| ())
+        .map_err(|e| format!("{:#}", e))
+}
+
+#[test]
+fn mfa_by_amr_or_acr() {
+    let (_mock, oauth_client) = client_requiring(json!({
+        "amr": ["mfa"],
+        "acr": ["urn:example:loa:2"],
+    }));
+
+    assert!(validate(&oauth_client, json!({ "amr": ["pwd", "mfa"] })).is_ok());
+    assert!(validate(&oauth_client, json!({ "acr": "urn:example:loa:2" })).is_ok());
+    assert_eq!(
+        validate(&oauth_client, json!({ "amr": ["pwd"], "acr": "1" })).unwrap_err(),
+        "Claim 'acr' is \"1\", expected one of [\"urn:example:loa:2\"], and claim 'amr' is [\"pwd\"], expected to contain [\"mfa\"]"
+    );
+}
+
+#[test]
+fn amr_only() {
+    let (_mock, oauth_client) = client_requiring(json!({ "amr": ["mfa"] }));
+
+    assert!(validate(&oauth_client, json!({ "amr": ["mfa"] })).is_ok());
+    assert_eq!(
+        validate(&oauth_client, json!({})).unwrap_err(),
+        "Claim 'amr' is missing, expected to contain [\"mfa\"]"
+    );
+}
+
+#[test]
+fn email_verified() {
+    let (_mock, oauth_client) = client_requiring(json!({ "email_verified": true }));
+
+    assert!(validate(&oauth_client, json!({ "email_verified": true })).is_ok());
+    assert_eq!(
+        validate(&oauth_client, json!({ "email_verified": false })).unwrap_err(),
+        "Claim 'email_verified' is false, expected true"
+    );
+    assert_eq!(
+        validate(&oauth_client, json!({})).unwrap_err(),
+        "Claim 'email_verified' is missing, expected true"
+    );
+}
+
+#[test]
+fn required_claims() {
+    let (_mock, oauth_client) = client_requiring(json!({
+        "required": { "tid": "tenant-a", "roles": "ssh", "sid": null }
+    }));
+
+    let ok = json!({ "tid": "tenant-a", "roles": ["web", "ssh"], "sid": "abc" });
+    assert!(validate(&oauth_client, ok).is_ok());
+
+    let wrong_tenant = json!({ "tid": "tenant-b", "roles": ["ssh"], "sid": "abc" });
+    assert_eq!(
+        validate(&oauth_client, wrong_tenant).unwrap_err(),
+        "Claim 'tid' is \"tenant-b\", expected \"tenant-a\""
+    );
+
+    let no_sid = json!({ "tid": "tenant-a", "roles": ["ssh"] });
+    assert_eq!(
+        validate(&oauth_client, no_sid).unwrap_err(),
+        "Claim 'sid' is missing, expected to be present"
+    );
+}
+
+#[test]
+fn leeway() {
+    let expired = json!({ "exp": (Utc::now() - Duration::seconds(120)).timestamp() });
+
+    let (_mock, oauth_client) = client_requiring(json!({ "leeway": 300 }));
+    assert!(validate(&oauth_client, expired.clone()).is_ok());
+
+    let (_mock, oauth_client) = client_requiring(json!({ "leeway": 0 }));
+    assert_eq!(
+        validate(&oauth_client, expired).unwrap_err(),
+        "Failed to decode JWT: ExpiredSignature"
+    );
+
+    let not_yet_valid = json!({ "nbf": (Utc::now() + Duration::seconds(120)).timestamp() });
+    assert_eq!(
+        validate(&oauth_client, not_yet_valid).unwrap_err(),
+        "Failed to decode JWT: ImmatureSignature"
+    );
+}
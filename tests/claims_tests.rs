@@ -0,0 +1,112 @@
+mod utils;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::Algorithm;
+use oauth2::AccessToken;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::json;
+use utils::{id_token_claims, mock_config, sign_token, Mock};
+
+#[test]
+fn verified_claims_from_id_token() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    let mut claims = id_token_claims(&mock, "test");
+    claims["groups"] = json!(["admins"]);
+    let id_token = sign_token(Algorithm::RS256, &claims);
+    mock.http_device_complete();
+    mock.http_token_with_id_token(&id_token);
+
+    let device_details = oauth_client.device_code().unwrap();
+    let token = oauth_client.get_token(&device_details, None).unwrap();
+    let claims = oauth_client.verify(&token).unwrap();
+
+    assert_eq!(claims.username(), "test");
+    assert_eq!(claims.sub(), Some("test-sub"));
+    assert_eq!(claims.groups(), Some(&["admins".to_string()][..]));
+    assert!(oauth_client.authorize(&claims, "test"));
+    assert!(!oauth_client.authorize(&claims, "other"));
+}
+
+#[test]
+fn every_key_type_is_verified() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    for alg in [Algorithm::RS256, Algorithm::PS256, Algorithm::ES256, Algorithm::EdDSA] {
+        let id_token = sign_token(alg, &id_token_claims(&mock, "test"));
+        let claims = oauth_client
+            .validate_token_claims(&AccessToken::new(id_token))
+            .unwrap_or_else(|e| panic!("{:?} token rejected: {:#}", alg, e));
+        assert_eq!(claims.username(), "test");
+    }
+}
+
+#[test]
+fn tampered_payload_is_rejected() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    let id_token = sign_token(Algorithm::RS256, &id_token_claims(&mock, "test"));
+    let forged_payload = URL_SAFE_NO_PAD.encode(id_token_claims(&mock, "admin").to_string());
+    let parts: Vec<&str> = id_token.split('.').collect();
+    let forged = format!("{}.{}.{}", parts[0], forged_payload, parts[2]);
+
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(forged))
+        .unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "Failed to decode JWT: InvalidSignature"
+    );
+}
+
+#[test]
+fn foreign_issuer_is_rejected() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    let mut claims = id_token_claims(&mock, "test");
+    claims["iss"] = json!("https://evil.example.com");
+    let id_token = sign_token(Algorithm::RS256, &claims);
+
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap_err();
+    assert_eq!(format!("{:#}", err), "Failed to decode JWT: InvalidIssuer");
+}
+
+#[test]
+fn disallowed_algorithm_is_rejected() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    let mut config = mock_config(&mock.server.url(), None);
+    config.allowed_algorithms = vec![Algorithm::RS256];
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let id_token = sign_token(Algorithm::ES256, &id_token_claims(&mock, "test"));
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Token signed with disallowed algorithm ES256"
+    );
+}
+
+#[test]
+fn opaque_id_token_is_rejected() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+
+    mock.http_device_complete();
+    mock.http_token_with_status(200);
+
+    let device_details = oauth_client.device_code().unwrap();
+    let token = oauth_client.get_token(&device_details, None).unwrap();
+    let err = oauth_client.verify(&token).unwrap_err();
+
+    assert!(err.to_string().starts_with("Failed to decode token header"));
+}
@@ -0,0 +1,198 @@
+mod utils;
+
+use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+use mockito::Matcher;
+use pam_oauth2_device::client_auth::{ClientAssertion, CLIENT_ASSERTION_TYPE};
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::jwks::Jwks;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Map, Value};
+use url::Url;
+use utils::{fixture, mock_config, Mock};
+
+const TOKEN_RESPONSE: &str = r#"{
+    "access_token": "mocking_access_token",
+    "token_type": "Bearer",
+    "expires_in": 3600
+}"#;
+
+fn client_with(mock: &Mock, auth: Value) -> Result<OAuthClient, String> {
+    let base = mock_config(&mock.server.url(), None);
+    let mut config = serde_json::to_value(&*base).unwrap();
+    for (k, v) in auth.as_object().unwrap() {
+        config[k] = v.clone();
+    }
+    let config: Config = serde_json::from_value(config).unwrap();
+    OAuthClient::new(&config).map_err(|e| format!("{:#}", e))
+}
+
+fn private_key_jwt() -> Value {
+    json!({
+        "client_secret": null,
+        "token_endpoint_auth_method": "private_key_jwt",
+        "client_assertion_key": fixture("rsa_private.pem"),
+        "client_assertion_kid": "rsa-key",
+    })
+}
+
+#[test]
+fn client_secret_post_by_default() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client_with(&mock, json!({})).unwrap();
+
+    mock.http_device_complete();
+    let token = mock
+        .server
+        .mock("POST", "/token")
+        .match_header("authorization", Matcher::Missing)
+        .match_body(Matcher::UrlEncoded("client_secret".into(), "test".into()))
+        .with_body(TOKEN_RESPONSE)
+        .create();
+
+    let details = oauth_client.device_code().unwrap();
+    oauth_client.get_token(&details, None).unwrap();
+    token.assert();
+}
+
+#[test]
+fn client_secret_basic() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client_with(
+        &mock,
+        json!({ "client_secret": "s3cr3t/+", "token_endpoint_auth_method": "client_secret_basic" }),
+    )
+    .unwrap();
+
+    // "test:s3cr3t%2F%2B", the secret is form-encoded before base64
+    let basic = "Basic dGVzdDpzM2NyM3QlMkYlMkI=";
+    let device = mock
+        .server
+        .mock("POST", "/device")
+        .match_header("authorization", basic)
+        .with_body(r#"{ "device_code": "d", "user_code": "u", "verification_uri": "https://mocking.uri/", "expires_in": 60 }"#)
+        .create();
+    let token = mock
+        .server
+        .mock("POST", "/token")
+        .match_header("authorization", basic)
+        .with_body(TOKEN_RESPONSE)
+        .create();
+
+    let details = oauth_client.device_code().unwrap();
+    oauth_client.get_token(&details, None).unwrap();
+    device.assert();
+    token.assert();
+}
+
+#[test]
+fn public_client() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client_with(&mock, json!({ "client_secret": null })).unwrap();
+
+    mock.http_device_complete();
+    let token = mock
+        .server
+        .mock("POST", "/token")
+        .match_header("authorization", Matcher::Missing)
+        .match_body(
+            "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code&client_id=test&device_code=mocking_device_code",
+        )
+        .with_body(TOKEN_RESPONSE)
+        .create();
+
+    let details = oauth_client.device_code().unwrap();
+    oauth_client.get_token(&details, None).unwrap();
+    token.assert();
+}
+
+#[test]
+fn missing_secret() {
+    let (mock, _) = Mock::builder().init(None);
+    let err = client_with(
+        &mock,
+        json!({ "client_secret": null, "token_endpoint_auth_method": "client_secret_basic" }),
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        "client_secret is required unless token_endpoint_auth_method is private_key_jwt or none"
+    );
+}
+
+#[test]
+fn private_key_jwt_requests() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client_with(&mock, private_key_jwt()).unwrap();
+
+    let assertion = Matcher::AllOf(vec![
+        Matcher::UrlEncoded("client_assertion_type".into(), CLIENT_ASSERTION_TYPE.into()),
+        Matcher::Regex("client_assertion=[\\w-]+\\.[\\w-]+\\.[\\w-]+".into()),
+    ]);
+    let device = mock
+        .server
+        .mock("POST", "/device")
+        .match_body(assertion.clone())
+        .with_body(r#"{ "device_code": "d", "user_code": "u", "verification_uri": "https://mocking.uri/", "expires_in": 60 }"#)
+        .create();
+    let token = mock
+        .server
+        .mock("POST", "/token")
+        .match_header("authorization", Matcher::Missing)
+        .match_body(assertion)
+        .with_body(TOKEN_RESPONSE)
+        .create();
+
+    let details = oauth_client.device_code().unwrap();
+    oauth_client.get_token(&details, None).unwrap();
+    device.assert();
+    token.assert();
+}
+
+#[test]
+fn assertion_claims() {
+    let (mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), None);
+    config.client_assertion_key = Some(fixture("rsa_private.pem"));
+    config.client_assertion_kid = Some("rsa-key".to_string());
+
+    let audience = Url::parse(&format!("{}/token", mock.server.url())).unwrap();
+    let assertion = ClientAssertion::load(&config).unwrap();
+    let first = assertion.sign(&audience).unwrap();
+    let second = assertion.sign(&audience).unwrap();
+
+    let jwks: Jwks = serde_json::from_str(include_str!("fixtures/jwks.json")).unwrap();
+    let key = jwks.keys.iter().find(|k| k.kid.as_deref() == Some("rsa-key")).unwrap();
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[audience.as_str()]);
+    validation.set_issuer(&["test"]);
+    validation.set_required_spec_claims(&["exp", "aud", "iss", "sub"]);
+
+    let decode_claims = |jwt: &str| {
+        decode::<Map<String, Value>>(jwt, &key.decoding_key(Algorithm::RS256).unwrap(), &validation)
+            .unwrap()
+            .claims
+    };
+    let claims = decode_claims(&first);
+    assert_eq!(claims["sub"], "test");
+    assert_ne!(claims["jti"], decode_claims(&second)["jti"]);
+    assert_eq!(decode_header(&first).unwrap().kid.as_deref(), Some("rsa-key"));
+}
+
+#[test]
+fn certificate_thumbprints() {
+    let (mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), None);
+    config.client_assertion_key = Some(fixture("rsa_private.pem"));
+    config.client_assertion_certificate = Some(fixture("rsa_cert.pem"));
+
+    let audience = Url::parse("https://login.example.com/token").unwrap();
+    let jwt = ClientAssertion::load(&config).unwrap().sign(&audience).unwrap();
+    let header = decode_header(&jwt).unwrap();
+
+    assert_eq!(header.x5t.as_deref(), Some("oRTMtu54d8gMu3VMCYPuxOUSB8w"));
+    assert_eq!(
+        header.x5t_s256.as_deref(),
+        Some("vEnnfj15nrTQIoMFCvF5TLLF-WW12jNS09O_9_0Zr5Y")
+    );
+}
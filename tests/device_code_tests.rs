@@ -75,6 +75,21 @@ fn device_uri_complete() {
     );
 }
 
+#[test]
+fn device_auto_poll_prompt() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.http_device_complete();
+
+    let resp = oauth_client.device_code().unwrap();
+    let mut prompt = UserPrompt::new(&resp, &Messages::default());
+    prompt.set_auto_poll(true);
+
+    assert_eq!(
+        prompt.to_string(),
+        "\nOpen the following link in your web browser:\nhttps://mocking.uri/mocking_user_code\nPress \"ENTER\" after successful authentication, or type \"q\" to cancel..."
+    );
+}
+
 #[test]
 fn err_500_device() {
     let (mut mock, oauth_client) = Mock::builder().init(None);
@@ -96,6 +111,9 @@ fn err_500_device() {
     let _ = resp.map_err(|err| TestLogger::handle_error(err, "Failed to get device code"));
     assert_eq!(
         logger.msg(),
-        "Failed to get device code\n    caused by: Server returned error response"
+        format!(
+            "Failed to get device code\n    caused by: Request failed\n    caused by: HTTP status server error (500 Internal Server Error) for url ({}/device)",
+            mock.server.url()
+        )
     );
 }
@@ -0,0 +1,146 @@
+use mockito::Server;
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+
+fn issuer_config(issuer: &str, extra: Value) -> Config {
+    let cache_dir = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-discovery-{}-{}",
+        std::process::id(),
+        issuer.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    ));
+    let mut config = json!({
+        "client_id": "test",
+        "client_secret": "test",
+        "issuer": issuer,
+        "cache_dir": cache_dir,
+    });
+    for (k, v) in extra.as_object().unwrap() {
+        config[k] = v.clone();
+    }
+    serde_json::from_value(config).unwrap()
+}
+
+fn discovery_body(issuer: &str) -> String {
+    json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/auth", issuer),
+        "device_authorization_endpoint": format!("{}/device", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/certs", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "revocation_endpoint": format!("{}/revoke", issuer),
+    })
+    .to_string()
+}
+
+#[test]
+fn discovers_endpoints_from_issuer() {
+    let mut server = Server::new();
+    let issuer = format!("{}/realms/test", server.url());
+    let well_known = server
+        .mock("GET", "/realms/test/.well-known/openid-configuration")
+        .with_status(200)
+        .with_body(discovery_body(&issuer))
+        .expect(1)
+        .create();
+
+    let config = issuer_config(&issuer, json!({}));
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    let resolved = oauth_client.config();
+
+    assert_eq!(
+        resolved.oauth_device_url.as_ref().unwrap().as_str(),
+        format!("{}/device", issuer)
+    );
+    assert_eq!(
+        resolved.oauth_token_url.as_ref().unwrap().as_str(),
+        format!("{}/token", issuer)
+    );
+    assert_eq!(
+        resolved.jwks_url().unwrap().as_str(),
+        format!("{}/certs", issuer)
+    );
+    assert_eq!(
+        resolved.revocation_url.as_ref().unwrap().as_str(),
+        format!("{}/revoke", issuer)
+    );
+
+    // The second client is served from the on-disk cache
+    OAuthClient::new(&config).unwrap();
+    well_known.assert();
+}
+
+#[test]
+fn explicit_url_overrides_discovery() {
+    let mut server = Server::new();
+    let issuer = format!("{}/override", server.url());
+    server
+        .mock("GET", "/override/.well-known/openid-configuration")
+        .with_status(200)
+        .with_body(discovery_body(&issuer))
+        .create();
+
+    let config = issuer_config(
+        &issuer,
+        json!({ "oauth_token_url": "https://token.example.com/token" }),
+    );
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    assert_eq!(
+        oauth_client.config().oauth_token_url.as_ref().unwrap().as_str(),
+        "https://token.example.com/token"
+    );
+    assert_eq!(
+        oauth_client.config().oauth_device_url.as_ref().unwrap().as_str(),
+        format!("{}/device", issuer)
+    );
+}
+
+#[test]
+fn issuer_mismatch() {
+    let mut server = Server::new();
+    let issuer = format!("{}/mismatch", server.url());
+    server
+        .mock("GET", "/mismatch/.well-known/openid-configuration")
+        .with_status(200)
+        .with_body(discovery_body("https://evil.example.com"))
+        .create();
+
+    let config = issuer_config(&issuer, json!({}));
+    let err = OAuthClient::new(&config).unwrap_err();
+
+    assert!(format!("{:#}", err).contains("does not match configured issuer"));
+}
+
+#[test]
+fn discovers_introspection_endpoint_when_needed() {
+    let mut server = Server::new();
+    let issuer = format!("{}/introspection", server.url());
+    let mut body: Value = serde_json::from_str(&discovery_body(&issuer)).unwrap();
+    body["introspection_endpoint"] = json!(format!("{}/introspect", issuer));
+    let well_known = server
+        .mock("GET", "/introspection/.well-known/openid-configuration")
+        .with_status(200)
+        .with_body(body.to_string())
+        .expect(1)
+        .create();
+
+    let config = issuer_config(
+        &issuer,
+        json!({
+            "oauth_auth_url": format!("{}/auth", issuer),
+            "oauth_device_url": format!("{}/device", issuer),
+            "oauth_token_url": format!("{}/token", issuer),
+            "jwks_url": format!("{}/certs", issuer),
+            "token_validation": "introspection",
+        }),
+    );
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    well_known.assert();
+    assert_eq!(
+        oauth_client.config().oauth_token_introspect_url.as_ref().unwrap().as_str(),
+        format!("{}/introspect", issuer)
+    );
+}
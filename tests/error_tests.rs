@@ -0,0 +1,135 @@
+mod utils;
+
+use oauth2::AccessToken;
+use pam::constants::PamResultCode;
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::error::{AuthError, ErrorClass};
+use pam_oauth2_device::oauth_device::OAuthClient;
+use pam_oauth2_device::poller::PollError;
+use serde_json::json;
+use utils::{mock_config, Mock};
+
+fn device_error(mock: &mut Mock, status: usize) -> ErrorClass {
+    mock.server
+        .mock("POST", "/device")
+        .with_status(status)
+        .with_body(r#"{ "error": "invalid_client" }"#)
+        .create();
+    let oauth_client = OAuthClient::new(&mock_config(&mock.server.url(), None)).unwrap();
+    let err = oauth_client.device_code().unwrap_err();
+    AuthError::request(ErrorClass::Config, "Failed to receive device code response", err).class()
+}
+
+#[test]
+fn pam_codes() {
+    assert_eq!(ErrorClass::Unavailable.pam_code(), PamResultCode::PAM_AUTHINFO_UNAVAIL);
+    assert_eq!(ErrorClass::Config.pam_code(), PamResultCode::PAM_SYSTEM_ERR);
+    assert_eq!(ErrorClass::Denied.pam_code(), PamResultCode::PAM_AUTH_ERR);
+    assert_eq!(ErrorClass::Timeout.pam_code(), PamResultCode::PAM_MAXTRIES);
+    assert_eq!(ErrorClass::Policy.pam_code(), PamResultCode::PAM_PERM_DENIED);
+}
+
+#[test]
+fn fail_open_ignores_listed_classes() {
+    let (mock, _) = Mock::builder().init(None);
+    let base = mock_config(&mock.server.url(), None);
+    let mut config = serde_json::to_value(&*base).unwrap();
+    config["fail_open"] = json!(["unavailable", "timeout"]);
+    let config: Config = serde_json::from_value(config).unwrap();
+
+    assert_eq!(
+        ErrorClass::Unavailable.resolve(&config.fail_open),
+        PamResultCode::PAM_IGNORE
+    );
+    assert_eq!(
+        ErrorClass::Timeout.resolve(&config.fail_open),
+        PamResultCode::PAM_IGNORE
+    );
+    assert_eq!(
+        ErrorClass::Denied.resolve(&config.fail_open),
+        PamResultCode::PAM_AUTH_ERR
+    );
+}
+
+#[test]
+fn fail_closed_by_default() {
+    let (mock, _) = Mock::builder().init(None);
+    let config = mock_config(&mock.server.url(), None);
+
+    assert!(config.fail_open.is_empty());
+    assert_eq!(
+        ErrorClass::Unavailable.resolve(&config.fail_open),
+        PamResultCode::PAM_AUTHINFO_UNAVAIL
+    );
+}
+
+#[test]
+fn unreachable_provider_is_unavailable() {
+    // Nothing listens on the discard port
+    let config = mock_config(&"http://127.0.0.1:9".to_string(), None);
+    let err = OAuthClient::new(&config).unwrap().device_code().unwrap_err();
+
+    let err = AuthError::request(ErrorClass::Config, "Failed to receive device code response", err);
+    assert_eq!(err.class(), ErrorClass::Unavailable);
+}
+
+#[test]
+fn server_error_is_unavailable() {
+    let (mut mock, _) = Mock::builder().init(None);
+    assert_eq!(device_error(&mut mock, 503), ErrorClass::Unavailable);
+}
+
+#[test]
+fn error_response_keeps_class() {
+    let (mut mock, _) = Mock::builder().init(None);
+    assert_eq!(device_error(&mut mock, 401), ErrorClass::Config);
+}
+
+#[test]
+fn jwks_outage_is_unavailable() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.server.mock("GET", "/jwks").with_status(503).create();
+
+    let id_token = utils::sign_token(
+        jsonwebtoken::Algorithm::RS256,
+        &utils::id_token_claims(&mock, "test"),
+    );
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap_err();
+    let err = AuthError::request(ErrorClass::Denied, "Failed to verify user token", err);
+    assert_eq!(err.class(), ErrorClass::Unavailable);
+}
+
+#[test]
+fn malformed_jwks_keeps_class() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.server.mock("GET", "/jwks").with_body("<html></html>").create();
+
+    let id_token = utils::sign_token(
+        jsonwebtoken::Algorithm::RS256,
+        &utils::id_token_claims(&mock, "test"),
+    );
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap_err();
+    let err = AuthError::request(ErrorClass::Denied, "Failed to verify user token", err);
+    assert_eq!(err.class(), ErrorClass::Denied);
+}
+
+#[test]
+fn poll_outcomes() {
+    // Nothing listens on the discard port
+    let unreachable = reqwest::blocking::get("http://127.0.0.1:9").unwrap_err();
+    let denied = serde_json::from_value(json!({ "error": "access_denied" })).unwrap();
+    let classes = [
+        (PollError::AccessDenied(denied), ErrorClass::Denied),
+        (PollError::ExpiredToken, ErrorClass::Timeout),
+        (PollError::Timeout, ErrorClass::Timeout),
+        (PollError::request(unreachable), ErrorClass::Unavailable),
+        (PollError::request(serde_json::from_str::<u32>("{").unwrap_err()), ErrorClass::Denied),
+    ];
+    for (err, class) in classes {
+        assert_eq!(AuthError::from(err).class(), class);
+    }
+}
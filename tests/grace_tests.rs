@@ -0,0 +1,178 @@
+mod utils;
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use pam_oauth2_device::claims::VerifiedClaims;
+use pam_oauth2_device::grace::{GraceCache, GraceKey};
+use serde_json::{json, Value};
+use utils::{mock_config, verified_claims, Mock};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn verified(exp: i64) -> VerifiedClaims {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = mock_config(&mock.server.url(), None);
+    verified_claims(&config, json!({ "exp": exp, "groups": ["ops"] })).unwrap()
+}
+
+fn grace_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-grace-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn key() -> GraceKey {
+    GraceKey {
+        user: "test".to_string(),
+        service: "sudo".to_string(),
+        tty: Some("/dev/pts/3".to_string()),
+        session_id: 4242,
+        session_start: 123456,
+    }
+}
+
+fn entry(dir: &Path) -> PathBuf {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|e| e == "json"))
+        .unwrap()
+}
+
+#[test]
+fn reused_within_period() {
+    let now = chrono::Utc::now().timestamp();
+    let grace = GraceCache::new(grace_dir("reuse"));
+    grace.record(&key(), &verified(now + 3600), Duration::from_secs(300), now).unwrap();
+
+    let claims = grace.lookup(&key(), now + 299).unwrap();
+    assert_eq!(claims.username(), "test");
+    assert_eq!(claims.groups(), Some(&["ops".to_string()][..]));
+    assert_eq!(claims.sub(), Some("test-sub"));
+
+    assert!(grace.lookup(&key(), now + 300).is_none());
+}
+
+#[test]
+fn never_beyond_token_exp() {
+    let now = chrono::Utc::now().timestamp();
+    let grace = GraceCache::new(grace_dir("exp"));
+    grace.record(&key(), &verified(now + 60), HOUR, now).unwrap();
+
+    assert!(grace.lookup(&key(), now + 59).is_some());
+    assert!(grace.lookup(&key(), now + 60).is_none());
+}
+
+#[test]
+fn bound_to_login() {
+    let now = chrono::Utc::now().timestamp();
+    let grace = GraceCache::new(grace_dir("key"));
+    grace.record(&key(), &verified(now + 3600), HOUR, now).unwrap();
+
+    let other_service = GraceKey { service: "su".to_string(), ..key() };
+    let other_tty = GraceKey { tty: Some("/dev/pts/4".to_string()), ..key() };
+    let later_session = GraceKey { session_start: 999999, ..key() };
+    let other_user = GraceKey { user: "root".to_string(), ..key() };
+    for key in [other_service, other_tty, later_session, other_user] {
+        assert!(grace.lookup(&key, now + 1).is_none(), "{:?}", key);
+    }
+    assert!(grace.lookup(&key(), now + 1).is_some());
+}
+
+#[test]
+fn clock_set_back() {
+    let now = chrono::Utc::now().timestamp();
+    let grace = GraceCache::new(grace_dir("clock"));
+    grace.record(&key(), &verified(now + 3600), HOUR, now).unwrap();
+
+    assert!(grace.lookup(&key(), now - 1).is_none());
+}
+
+#[test]
+fn tampered_entry_is_removed() {
+    let now = chrono::Utc::now().timestamp();
+    let dir = grace_dir("tamper");
+    let grace = GraceCache::new(&dir);
+    grace.record(&key(), &verified(now + 3600), Duration::from_secs(60), now).unwrap();
+
+    let path = entry(&dir);
+    let mut signed: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let mut inner: Value = serde_json::from_str(signed["entry"].as_str().unwrap()).unwrap();
+    inner["expires_at"] = json!(now + 86400);
+    signed["entry"] = json!(inner.to_string());
+    std::fs::write(&path, signed.to_string()).unwrap();
+
+    assert!(grace.lookup(&key(), now + 120).is_none());
+    assert!(!path.exists());
+}
+
+#[test]
+fn accessible_entry_is_ignored() {
+    let now = chrono::Utc::now().timestamp();
+    let dir = grace_dir("mode");
+    let grace = GraceCache::new(&dir);
+    grace.record(&key(), &verified(now + 3600), HOUR, now).unwrap();
+
+    let path = entry(&dir);
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(grace.lookup(&key(), now + 1).is_none());
+
+    grace.record(&key(), &verified(now + 3600), HOUR, now).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(grace.lookup(&key(), now + 1).is_none());
+}
+
+#[test]
+fn kept_when_a_session_of_the_login_closes() {
+    let now = chrono::Utc::now().timestamp();
+    let grace = GraceCache::new(grace_dir("close"));
+    let current = GraceKey::current("test", "sudo", Some("/dev/pts/3")).unwrap();
+    grace.record(&current, &verified(now + 3600), HOUR, now).unwrap();
+
+    grace.close(&current).unwrap();
+    assert!(grace.lookup(&current, now + 1).is_some());
+}
+
+#[test]
+fn removed_when_the_login_has_ended() {
+    let now = chrono::Utc::now().timestamp();
+    let dir = grace_dir("ended");
+    let grace = GraceCache::new(&dir);
+    grace.record(&key(), &verified(now + 3600), HOUR, now).unwrap();
+    let path = entry(&dir);
+
+    grace.close(&key()).unwrap();
+    assert!(!path.exists());
+    grace.close(&key()).unwrap();
+}
+
+#[test]
+fn expired_entries_are_pruned() {
+    let now = chrono::Utc::now().timestamp();
+    let dir = grace_dir("prune");
+    let grace = GraceCache::new(&dir);
+    let other = GraceKey {
+        session_id: 4343,
+        ..key()
+    };
+    grace.record(&other, &verified(now + 3600), Duration::from_secs(60), now).unwrap();
+    let expired = entry(&dir);
+
+    grace.record(&key(), &verified(now + 3600), HOUR, now + 60).unwrap();
+    assert!(!expired.exists());
+    assert!(grace.lookup(&key(), now + 61).is_some());
+}
+
+#[test]
+fn current_session() {
+    let key = GraceKey::current("test", "sudo", None).unwrap();
+    assert_eq!(key.session_id, nix::unistd::getsid(None).unwrap().as_raw());
+    assert!(key.session_start > 0);
+}
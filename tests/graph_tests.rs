@@ -0,0 +1,194 @@
+mod utils;
+
+use jsonwebtoken::Algorithm;
+use mockito::Matcher;
+use oauth2::AccessToken;
+use pam_oauth2_device::error::{AuthError, ErrorClass};
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+use url::Url;
+use utils::{id_token_claims, mock_config, sign_token, Mock, MockConfig};
+
+const OID: &str = "6f1c3a52-8d2e-4a5b-9c1f-2b7e4d9a0c11";
+const ALLOWED_GROUP: &str = "a2b4c6d8-0000-4000-8000-000000000001";
+
+fn graph_config(mock: &Mock) -> MockConfig {
+    let mut config = mock_config(&mock.server.url(), None);
+    config.allowed_groups = Some(vec![ALLOWED_GROUP.to_string()]);
+    config.graph_url = Url::parse(&format!("{}/graph", mock.server.url())).unwrap();
+    config
+}
+
+fn overage_claims(mock: &Mock) -> Value {
+    let mut claims = id_token_claims(mock, "test");
+    claims["oid"] = json!(OID);
+    claims["_claim_names"] = json!({ "groups": "src1" });
+    claims["_claim_sources"] = json!({
+        "src1": { "endpoint": format!("https://graph.windows.net/tenant/users/{}/getMemberObjects", OID) }
+    });
+    claims
+}
+
+fn app_token(mock: &mut Mock) -> mockito::Mock {
+    mock.server
+        .mock("POST", "/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "client_credentials".into()),
+            Matcher::UrlEncoded("scope".into(), format!("{}/graph/.default", mock.server.url())),
+            Matcher::UrlEncoded("client_secret".into(), "test".into()),
+        ]))
+        .with_body(r#"{ "access_token": "app_token", "token_type": "Bearer", "expires_in": 3600 }"#)
+        .create()
+}
+
+fn member_objects(mock: &mut Mock, groups: &[&str]) -> mockito::Mock {
+    mock.server
+        .mock("POST", format!("/graph/v1.0/users/{}/getMemberObjects", OID).as_str())
+        .match_header("authorization", "Bearer app_token")
+        .match_body(Matcher::Json(json!({ "securityEnabledOnly": false })))
+        .with_body(json!({ "value": groups }).to_string())
+        .create()
+}
+
+fn validate(oauth_client: &OAuthClient, claims: &Value) -> anyhow::Result<Option<Vec<String>>> {
+    let id_token = sign_token(Algorithm::RS256, claims);
+    oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .map(|claims| claims.groups().map(<[String]>::to_vec))
+}
+
+#[test]
+fn claim_names_overage_is_resolved() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let token = app_token(&mut mock);
+    let members = member_objects(&mut mock, &["other-group", ALLOWED_GROUP]);
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let groups = validate(&oauth_client, &overage_claims(&mock)).unwrap();
+    assert_eq!(groups, Some(vec!["other-group".to_string(), ALLOWED_GROUP.to_string()]));
+    token.assert();
+    members.assert();
+}
+
+#[test]
+fn hasgroups_overage_is_authorized() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    app_token(&mut mock);
+    member_objects(&mut mock, &[ALLOWED_GROUP]);
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let mut claims = id_token_claims(&mock, "test");
+    claims["oid"] = json!(OID);
+    claims["hasgroups"] = json!(true);
+    let id_token = sign_token(Algorithm::RS256, &claims);
+    let verified = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap();
+
+    assert!(oauth_client.authorize(&verified, "test"));
+}
+
+#[test]
+fn membership_outside_allowed_groups_is_denied() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    app_token(&mut mock);
+    member_objects(&mut mock, &["other-group"]);
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let id_token = sign_token(Algorithm::RS256, &overage_claims(&mock));
+    let verified = oauth_client
+        .validate_token_claims(&AccessToken::new(id_token))
+        .unwrap();
+
+    assert!(!oauth_client.authorize(&verified, "test"));
+}
+
+#[test]
+fn groups_claim_needs_no_lookup() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let token = app_token(&mut mock).expect(0);
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let mut claims = id_token_claims(&mock, "test");
+    claims["groups"] = json!([ALLOWED_GROUP]);
+    assert_eq!(
+        validate(&oauth_client, &claims).unwrap(),
+        Some(vec![ALLOWED_GROUP.to_string()])
+    );
+    token.assert();
+}
+
+#[test]
+fn overage_without_oid() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let mut claims = overage_claims(&mock);
+    claims.as_object_mut().unwrap().remove("oid");
+    let err = validate(&oauth_client, &claims).unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "Failed to resolve group overage: No 'oid' claim to look up group membership for"
+    );
+}
+
+#[test]
+fn graph_outage_is_unavailable() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    app_token(&mut mock);
+    mock.server
+        .mock("POST", format!("/graph/v1.0/users/{}/getMemberObjects", OID).as_str())
+        .with_status(503)
+        .create();
+    let config = graph_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let err = validate(&oauth_client, &overage_claims(&mock)).unwrap_err();
+    let err = AuthError::request(ErrorClass::Denied, "Failed to verify user token", err);
+    assert_eq!(err.class(), ErrorClass::Unavailable);
+}
+
+#[test]
+fn overage_is_resolved_for_file_rules() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    app_token(&mut mock);
+    let members = member_objects(&mut mock, &[ALLOWED_GROUP]).expect(2);
+    let dir = std::env::temp_dir().join(format!("pam_oauth2_device-graph-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let account_map = dir.join("account_map.json");
+    std::fs::write(&account_map, json!({ "accounts": { "deploy": { "groups": [ALLOWED_GROUP] } } }).to_string()).unwrap();
+    let access_rules = dir.join("access_rules.json");
+    std::fs::write(
+        &access_rules,
+        json!({ "rules": [{ "name": "ops", "groups": [ALLOWED_GROUP], "action": "allow" }] }).to_string(),
+    )
+    .unwrap();
+
+    let mut config = graph_config(&mock);
+    config.allowed_groups = None;
+    config.account_map = Some(account_map);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    let verified = oauth_client
+        .validate_token_claims(&AccessToken::new(sign_token(Algorithm::RS256, &overage_claims(&mock))))
+        .unwrap();
+    assert!(oauth_client.authorize(&verified, "deploy"));
+
+    config.account_map = None;
+    config.access_rules = Some(access_rules);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    let groups = validate(&oauth_client, &overage_claims(&mock)).unwrap();
+    assert_eq!(groups, Some(vec![ALLOWED_GROUP.to_string()]));
+    members.assert();
+}
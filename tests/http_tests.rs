@@ -0,0 +1,105 @@
+mod utils;
+
+use std::time::Duration;
+
+use mockito::{Matcher, Server};
+use pam_oauth2_device::http::HttpClient;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use url::Url;
+use utils::{fixture, mock_config, Mock};
+
+const DEVICE_RESPONSE: &str = r#"{
+    "device_code": "mocking_device_code",
+    "user_code": "mocking_user_code",
+    "verification_uri": "https://mocking.uri/",
+    "expires_in": 3600
+}"#;
+
+#[test]
+fn user_agent() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    let default = mock
+        .server
+        .mock("POST", "/device")
+        .match_header("user-agent", Matcher::Regex("^pam_oauth2_device/".into()))
+        .with_body(DEVICE_RESPONSE)
+        .create();
+    oauth_client.device_code().unwrap();
+    default.assert();
+
+    let mut config = mock_config(&mock.server.url(), None);
+    config.user_agent = "bastion-login/1.0".to_string();
+    let custom = mock
+        .server
+        .mock("POST", "/device")
+        .match_header("user-agent", "bastion-login/1.0")
+        .with_body(DEVICE_RESPONSE)
+        .create();
+    OAuthClient::new(&config).unwrap().device_code().unwrap();
+    custom.assert();
+}
+
+#[test]
+fn requests_go_through_proxy() {
+    let mut proxy = Server::new();
+    // The provider itself is not resolvable, only the proxy can reach it
+    let mut config = mock_config(&"http://idp.invalid".to_string(), None);
+    config.https_proxy = Some(Url::parse(&proxy.url()).unwrap());
+
+    let device = proxy
+        .mock("POST", "/device")
+        .match_header("host", "idp.invalid")
+        .with_body(DEVICE_RESPONSE)
+        .create();
+    OAuthClient::new(&config).unwrap().device_code().unwrap();
+    device.assert();
+}
+
+#[test]
+fn no_proxy_bypasses_proxy() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), None);
+    // Nothing listens on the discard port
+    config.https_proxy = Some(Url::parse("http://127.0.0.1:9").unwrap());
+    config.no_proxy = Some("127.0.0.1,localhost".to_string());
+
+    let device = mock
+        .server
+        .mock("POST", "/device")
+        .with_body(DEVICE_RESPONSE)
+        .create();
+    OAuthClient::new(&config).unwrap().device_code().unwrap();
+    device.assert();
+}
+
+#[test]
+fn ca_bundle() {
+    let (mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), None);
+
+    config.ca_bundle = Some(fixture("rsa_cert.pem"));
+    assert!(HttpClient::new(&config).is_ok());
+
+    config.ca_bundle = Some(fixture("missing.pem"));
+    let err = HttpClient::new(&config).unwrap_err();
+    assert!(err.to_string().starts_with("Failed to read"));
+}
+
+#[test]
+fn slow_provider_times_out() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), None);
+    config.http_timeout = Duration::from_secs(1);
+
+    mock.server
+        .mock("POST", "/device")
+        .with_chunked_body(|w| {
+            std::thread::sleep(Duration::from_secs(3));
+            w.write_all(DEVICE_RESPONSE.as_bytes())
+        })
+        .create();
+
+    let start = std::time::Instant::now();
+    assert!(OAuthClient::new(&config).unwrap().device_code().is_err());
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
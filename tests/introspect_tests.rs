@@ -1,3 +1,5 @@
+#![allow(clippy::bool_assert_comparison)]
+
 mod test_logger;
 mod utils;
 
@@ -47,7 +49,7 @@ fn other_error() {
 
     assert_eq!(
         logger.msg(),
-        "Failed to intropsect user token\n    caused by: Request failed\n    caused by: curl request failed\n    caused by: [52] Server returned nothing (no headers, no data) (Empty reply from server)"
+        "Failed to intropsect user token\n    caused by: Other error: Server returned empty error response"
     );
 }
 #[test]
@@ -0,0 +1,227 @@
+mod utils;
+
+use jsonwebtoken::Algorithm;
+use mockito::Server;
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::http::HttpClient;
+use pam_oauth2_device::jwks::{Jwk, Jwks, JwksCache};
+use serde_json::json;
+use url::Url;
+
+fn cache_config(name: &str, refetch_interval: u64) -> Config {
+    let cache_dir = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-jwks-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let mut config = utils::mock_config(&"https://mocking.uri".to_string(), None).config;
+    config.cache_dir = cache_dir;
+    config.jwks_refetch_interval = std::time::Duration::from_secs(refetch_interval);
+    config
+}
+
+fn key_set(kids: &[&str]) -> String {
+    let keys: Vec<_> = kids
+        .iter()
+        .map(|kid| json!({ "kty": "RSA", "kid": kid, "n": "sXch", "e": "AQAB" }))
+        .collect();
+    json!({ "keys": keys }).to_string()
+}
+
+fn jwks_cache(config: &Config, server: &Server) -> JwksCache {
+    let url = Url::parse(&format!("{}/jwks", server.url())).unwrap();
+    JwksCache::new(config, &HttpClient::new(config).unwrap(), url)
+}
+
+#[test]
+fn key_set_is_cached() {
+    let mut server = Server::new();
+    let mock = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_header("cache-control", "public, max-age=600")
+        .with_body(key_set(&["key1"]))
+        .expect(1)
+        .create();
+
+    let config = cache_config("cached", 60);
+    let jwk = jwks_cache(&config, &server).find("key1").unwrap();
+    assert_eq!(jwk.kid.as_deref(), Some("key1"));
+
+    jwks_cache(&config, &server).find("key1").unwrap();
+    mock.assert();
+}
+
+#[test]
+fn unknown_kid_is_rate_limited() {
+    let mut server = Server::new();
+    let mock = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_body(key_set(&["key1"]))
+        .expect(1)
+        .create();
+
+    let config = cache_config("rate_limited", 60);
+    jwks_cache(&config, &server).find("key1").unwrap();
+
+    for _ in 0..3 {
+        let err = jwks_cache(&config, &server).find("forged").unwrap_err();
+        assert!(err.to_string().starts_with("No matching key for kid forged"));
+    }
+    mock.assert();
+}
+
+#[test]
+fn unknown_kid_refetches_after_interval() {
+    let mut server = Server::new();
+    let old_keys = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_body(key_set(&["key1"]))
+        .expect(1)
+        .create();
+
+    let config = cache_config("rotated", 0);
+    jwks_cache(&config, &server).find("key1").unwrap();
+    old_keys.assert();
+    old_keys.remove();
+
+    let new_keys = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_body(key_set(&["key1", "key2"]))
+        .expect(1)
+        .create();
+
+    let jwk = jwks_cache(&config, &server).find("key2").unwrap();
+    assert_eq!(jwk.kid.as_deref(), Some("key2"));
+    new_keys.assert();
+}
+
+#[test]
+fn no_store_is_not_reused() {
+    let mut server = Server::new();
+    let mock = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_header("cache-control", "no-store")
+        .with_body(key_set(&["key1"]))
+        .expect(2)
+        .create();
+
+    let config = cache_config("no_store", 60);
+    jwks_cache(&config, &server).find("key1").unwrap();
+    jwks_cache(&config, &server).find("key1").unwrap();
+    mock.assert();
+}
+
+#[test]
+fn unknown_kid_is_rate_limited_without_reuse() {
+    let mut server = Server::new();
+    let mock = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_header("cache-control", "no-store")
+        .with_body(key_set(&["key1"]))
+        .expect(1)
+        .create();
+
+    let config = cache_config("no_store_forged", 60);
+    jwks_cache(&config, &server).find("key1").unwrap();
+    for _ in 0..3 {
+        assert!(jwks_cache(&config, &server).find("forged").is_err());
+    }
+    mock.assert();
+}
+
+#[test]
+fn unknown_kid_is_rate_limited_without_cache() {
+    let mut server = Server::new();
+    let mock = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_body(key_set(&["key1"]))
+        .expect(1)
+        .create();
+
+    // A file where the cache directory should be makes every write fail
+    let mut config = cache_config("unwritable", 60);
+    std::fs::write(&config.cache_dir, "").unwrap();
+    config.cache_dir = config.cache_dir.join("cache");
+    for _ in 0..3 {
+        assert!(jwks_cache(&config, &server).find("forged").is_err());
+    }
+    mock.assert();
+}
+
+#[test]
+fn stale_key_set_survives_outage() {
+    let mut server = Server::new();
+    let keys = server
+        .mock("GET", "/jwks")
+        .with_status(200)
+        .with_header("cache-control", "no-cache")
+        .with_body(key_set(&["key1"]))
+        .create();
+
+    let config = cache_config("outage", 60);
+    jwks_cache(&config, &server).find("key1").unwrap();
+    keys.remove();
+
+    server.mock("GET", "/jwks").with_status(503).create();
+    let jwk = jwks_cache(&config, &server).find("key1").unwrap();
+    assert_eq!(jwk.kid.as_deref(), Some("key1"));
+}
+
+fn jwk(value: serde_json::Value) -> Jwk {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn key_type_selects_algorithms() {
+    let rsa = jwk(json!({ "kty": "RSA", "kid": "rsa", "n": "sXch", "e": "AQAB" }));
+    let ec = jwk(json!({ "kty": "EC", "kid": "ec", "crv": "P-256", "x": "eA", "y": "eQ" }));
+    let okp = jwk(json!({ "kty": "OKP", "kid": "okp", "crv": "Ed25519", "x": "eA" }));
+
+    assert!(rsa.decoding_key(Algorithm::RS256).is_ok());
+    assert!(rsa.decoding_key(Algorithm::PS256).is_ok());
+    assert!(ec.decoding_key(Algorithm::ES256).is_ok());
+    assert!(okp.decoding_key(Algorithm::EdDSA).is_ok());
+
+    assert!(rsa.decoding_key(Algorithm::ES256).is_err());
+    assert!(rsa.decoding_key(Algorithm::HS256).is_err());
+    assert!(ec.decoding_key(Algorithm::ES384).is_err());
+    assert!(okp.decoding_key(Algorithm::RS256).is_err());
+}
+
+#[test]
+fn key_restrictions_are_honoured() {
+    let ps_only = jwk(json!({ "kty": "RSA", "alg": "PS256", "n": "sXch", "e": "AQAB" }));
+    let enc_only = jwk(json!({ "kty": "RSA", "use": "enc", "n": "sXch", "e": "AQAB" }));
+
+    assert!(ps_only.decoding_key(Algorithm::PS256).is_ok());
+    assert_eq!(
+        ps_only
+            .decoding_key(Algorithm::RS256)
+            .err()
+            .unwrap()
+            .to_string(),
+        "Key is restricted to PS256, token uses RS256"
+    );
+    assert!(enc_only.decoding_key(Algorithm::RS256).is_err());
+}
+
+#[test]
+fn unsupported_keys_are_skipped() {
+    let jwks: Jwks = serde_json::from_value(json!({ "keys": [
+        { "kty": "oct", "kid": "hmac", "k": "c2VjcmV0" },
+        { "kty": "EC", "kid": "ec", "crv": "P-256", "x": "eA", "y": "eQ" },
+    ]}))
+    .unwrap();
+
+    assert_eq!(jwks.keys.len(), 1);
+    assert_eq!(jwks.keys[0].kid.as_deref(), Some("ec"));
+}
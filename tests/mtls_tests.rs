@@ -0,0 +1,196 @@
+mod utils;
+
+use jsonwebtoken::Algorithm;
+use mockito::Matcher;
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::http::HttpClient;
+use pam_oauth2_device::oauth_device::{DeviceTokenResponse, OAuthClient};
+use serde_json::{json, Value};
+use utils::{fixture, id_token_claims, mock_config, sign_token, Mock, MockConfig};
+
+// SHA-256 thumbprint of tests/fixtures/rsa_cert.pem
+const THUMBPRINT: &str = "vEnnfj15nrTQIoMFCvF5TLLF-WW12jNS09O_9_0Zr5Y";
+
+fn mtls_config(mock: &Mock) -> MockConfig {
+    let mut config = mock_config(&mock.server.url(), None);
+    config.client_certificate = Some(fixture("rsa_cert.pem"));
+    config.client_key = Some(fixture("rsa_private.pem"));
+    config
+}
+
+/// Verifies a token response whose JWT access token carries `cnf`.
+fn validate(mock: &Mock, oauth_client: &OAuthClient, cnf: Option<Value>) -> Result<(), String> {
+    let mut access_token = id_token_claims(mock, "test");
+    access_token["aud"] = json!("https://api.example.com");
+    if let Some(cnf) = cnf {
+        access_token["cnf"] = cnf;
+    }
+    verify(mock, oauth_client, &sign_token(Algorithm::RS256, &access_token))
+}
+
+/// Verifies a token response with `access_token` and an id_token, which
+/// never carries the binding.
+fn verify(mock: &Mock, oauth_client: &OAuthClient, access_token: &str) -> Result<(), String> {
+    let id_token = sign_token(Algorithm::RS256, &id_token_claims(mock, "test"));
+    let token: DeviceTokenResponse = serde_json::from_value(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+    .unwrap();
+    oauth_client
+        .verify(&token)
+        .map(|_| ())
+        .map_err(|e| format!("{:#}", e))
+}
+
+#[test]
+fn client_identity_is_loaded() {
+    let (mock, _) = Mock::builder().init(None);
+    let http = HttpClient::new(&mtls_config(&mock)).unwrap();
+    assert_eq!(http.certificate_thumbprint(), Some(THUMBPRINT));
+
+    let http = HttpClient::new(&mock_config(&mock.server.url(), None)).unwrap();
+    assert_eq!(http.certificate_thumbprint(), None);
+}
+
+#[test]
+fn certificate_without_key_is_rejected() {
+    let (mock, _) = Mock::builder().init(None);
+    let mut config = mtls_config(&mock);
+    config.client_key = None;
+
+    let err = HttpClient::new(&config).unwrap_err();
+    assert!(format!("{:#}", err).starts_with("Failed to load client certificate"));
+}
+
+#[test]
+fn tls_client_auth_requires_certificate() {
+    let (mock, _) = Mock::builder().init(None);
+    let base = mock_config(&mock.server.url(), None);
+    let mut config: Value = serde_json::to_value(&*base).unwrap();
+    config["client_secret"] = Value::Null;
+    config["token_endpoint_auth_method"] = json!("tls_client_auth");
+    let config: Config = serde_json::from_value(config).unwrap();
+
+    let err = OAuthClient::new(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "client_certificate is required for mutual TLS client authentication"
+    );
+}
+
+#[test]
+fn bound_token_matches_certificate() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = mtls_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    assert!(validate(&mock, &oauth_client, Some(json!({ "x5t#S256": THUMBPRINT }))).is_ok());
+    assert!(validate(&mock, &oauth_client, None).is_ok());
+    assert_eq!(
+        validate(&mock, &oauth_client, Some(json!({ "x5t#S256": "other" }))).unwrap_err(),
+        format!(
+            "Claim 'cnf.x5t#S256' is other, but the client certificate is {}",
+            THUMBPRINT
+        )
+    );
+}
+
+#[test]
+fn bound_token_without_certificate() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let mut config = mock_config(&mock.server.url(), None);
+    config.require_certificate_bound_tokens = true;
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    assert_eq!(
+        validate(&mock, &oauth_client, Some(json!({ "x5t#S256": THUMBPRINT }))).unwrap_err(),
+        format!(
+            "Claim 'cnf.x5t#S256' is {}, but the client certificate is not configured",
+            THUMBPRINT
+        )
+    );
+}
+
+#[test]
+fn binding_is_read_from_access_token() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = mtls_config(&mock);
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    // A binding on the id_token does not bind the access token
+    let mut id_token = id_token_claims(&mock, "test");
+    id_token["cnf"] = json!({ "x5t#S256": THUMBPRINT });
+    let id_token = sign_token(Algorithm::RS256, &id_token);
+    let token: DeviceTokenResponse = serde_json::from_value(json!({
+        "access_token": sign_token(Algorithm::RS256, &id_token_claims(&mock, "test")),
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+    .unwrap();
+    let mut config = mtls_config(&mock);
+    config.require_certificate_bound_tokens = true;
+    let strict = OAuthClient::new(&config).unwrap();
+    assert_eq!(
+        format!("{:#}", strict.verify(&token).unwrap_err()),
+        "Claim 'cnf.x5t#S256' is missing, token is not certificate-bound"
+    );
+    assert!(oauth_client.verify(&token).is_ok());
+}
+
+#[test]
+fn opaque_access_token_is_introspected() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let mut config = mtls_config(&mock);
+    config.require_certificate_bound_tokens = true;
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    mock.server
+        .mock("POST", "/introspect")
+        .match_body(Matcher::UrlEncoded("token".into(), "opaque".into()))
+        .with_body(json!({ "active": true, "cnf": { "x5t#S256": THUMBPRINT } }).to_string())
+        .create();
+
+    assert!(verify(&mock, &oauth_client, "opaque").is_ok());
+
+    config.oauth_token_introspect_url = None;
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    assert_eq!(
+        verify(&mock, &oauth_client, "opaque").unwrap_err(),
+        "Access token is opaque and no oauth_token_introspect_url is configured to check its certificate binding"
+    );
+}
+
+#[test]
+fn binding_can_be_required() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let mut config = mtls_config(&mock);
+    config.require_certificate_bound_tokens = true;
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    assert!(validate(&mock, &oauth_client, Some(json!({ "x5t#S256": THUMBPRINT }))).is_ok());
+    assert_eq!(
+        validate(&mock, &oauth_client, None).unwrap_err(),
+        "Claim 'cnf.x5t#S256' is missing, token is not certificate-bound"
+    );
+}
+
+#[test]
+fn unreadable_access_token_is_unbound_unless_required() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let mut config = mtls_config(&mock);
+    config.oauth_token_introspect_url = None;
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    assert!(verify(&mock, &oauth_client, "opaque").is_ok());
+    // Like a Microsoft Graph token, whose signature cannot be checked
+    assert!(verify(&mock, &oauth_client, "eyJhbGciOiJSUzI1NiJ9.e30.c2ln").is_ok());
+    // A binding that can be read is still enforced
+    assert!(validate(&mock, &oauth_client, Some(json!({ "x5t#S256": "other" }))).is_err());
+}
@@ -0,0 +1,257 @@
+mod utils;
+
+use std::time::{Duration, Instant};
+
+use oauth2::devicecode::DeviceCodeErrorResponse;
+use oauth2::StandardDeviceAuthorizationResponse;
+use pam_oauth2_device::config::Messages;
+use pam_oauth2_device::poller::{retry_after, CancelFlag, PollError, Poller};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use serde_json::json;
+use utils::Mock;
+
+fn details(interval: u64, expires_in: u64) -> StandardDeviceAuthorizationResponse {
+    serde_json::from_value(json!({
+        "device_code": "mocking_device_code",
+        "user_code": "mocking_user_code",
+        "verification_uri": "https://mocking.uri/",
+        "expires_in": expires_in,
+        "interval": interval,
+    }))
+    .unwrap()
+}
+
+fn error(code: &str) -> DeviceCodeErrorResponse {
+    serde_json::from_value(json!({ "error": code })).unwrap()
+}
+
+fn assert_between(wait: Duration, min: u64, max: u64) {
+    assert!(
+        wait >= Duration::from_secs(min) && wait <= Duration::from_secs(max),
+        "{:?} not in [{}s, {}s]",
+        wait,
+        min,
+        max
+    );
+}
+
+#[test]
+fn pending_waits_for_interval() {
+    let mut poller = Poller::new(&details(5, 600), None);
+    let wait = poller.on_error(error("authorization_pending"), None).unwrap();
+
+    assert_eq!(poller.interval(), Duration::from_secs(5));
+    // Jitter only ever adds to the interval
+    assert_between(wait, 5, 6);
+}
+
+#[test]
+fn slow_down_widens_interval() {
+    let mut poller = Poller::new(&details(5, 600), None);
+
+    poller.on_error(error("slow_down"), None).unwrap();
+    let wait = poller.on_error(error("slow_down"), None).unwrap();
+
+    assert_eq!(poller.interval(), Duration::from_secs(15));
+    assert_between(wait, 15, 17);
+}
+
+#[test]
+fn retry_after_extends_wait() {
+    let mut poller = Poller::new(&details(5, 600), None);
+
+    let wait = poller
+        .on_error(error("authorization_pending"), Some(Duration::from_secs(30)))
+        .unwrap();
+    assert_between(wait, 30, 33);
+
+    // A shorter Retry-After never polls faster than the interval
+    let wait = poller
+        .on_error(error("authorization_pending"), Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_between(wait, 5, 6);
+}
+
+#[test]
+fn terminal_errors() {
+    let mut poller = Poller::new(&details(5, 600), None);
+
+    assert!(matches!(
+        poller.on_error(error("access_denied"), None),
+        Err(PollError::AccessDenied(_))
+    ));
+    assert!(matches!(
+        poller.on_error(error("expired_token"), None),
+        Err(PollError::ExpiredToken)
+    ));
+    assert!(matches!(
+        poller.on_error(error("invalid_grant"), None),
+        Err(PollError::Server(_))
+    ));
+}
+
+#[test]
+fn outcomes_have_distinct_messages() {
+    let messages = Messages::default();
+    let outcomes = [
+        PollError::AccessDenied(error("access_denied")),
+        PollError::ExpiredToken,
+        PollError::Timeout,
+        PollError::Server(error("invalid_grant")),
+        PollError::Cancelled,
+    ];
+    let shown: Vec<&str> = outcomes.iter().map(|o| o.user_message(&messages)).collect();
+
+    assert_eq!(shown[0], messages.poll_access_denied);
+    assert_eq!(shown[1], messages.poll_expired);
+    assert_eq!(shown[2], messages.poll_timeout);
+    assert_eq!(shown[3], messages.poll_failed);
+    assert_eq!(shown[4], messages.poll_cancelled);
+}
+
+#[test]
+fn retry_after_header() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(retry_after(&headers), None);
+
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+    let date = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+    headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+    assert_between(retry_after(&headers).unwrap(), 58, 60);
+}
+
+#[test]
+fn huge_retry_after_is_bounded() {
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("18446744073709551615"));
+    let retry = retry_after(&headers).unwrap();
+    assert!(retry <= Duration::from_secs(24 * 60 * 60));
+
+    let mut poller = Poller::new(&details(5, 600), None);
+    let wait = poller
+        .on_error(error("authorization_pending"), Some(Duration::MAX))
+        .unwrap();
+    assert!(wait <= Duration::from_secs(661), "{:?}", wait);
+
+    let poller = Poller::new(&details(u64::MAX, u64::MAX), None);
+    poller.wait(Some(Duration::MAX));
+}
+
+fn pending_token_endpoint(mock: &mut Mock) {
+    mock.server
+        .mock("POST", "/token")
+        .with_status(400)
+        .with_body(r#"{ "error": "authorization_pending" }"#)
+        .create();
+}
+
+#[test]
+fn polling_stops_at_expires_in() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    pending_token_endpoint(&mut mock);
+
+    let start = Instant::now();
+    let err = oauth_client.get_token(&details(1, 2), None).unwrap_err();
+
+    assert!(matches!(err, PollError::ExpiredToken));
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn polling_stops_at_configured_timeout() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    pending_token_endpoint(&mut mock);
+
+    let start = Instant::now();
+    let err = oauth_client
+        .get_token(&details(1, 600), Some(Duration::from_secs(2)))
+        .unwrap_err();
+
+    assert!(matches!(err, PollError::Timeout));
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn expired_token_response() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    mock.server
+        .mock("POST", "/token")
+        .with_status(400)
+        .with_body(r#"{ "error": "expired_token" }"#)
+        .create();
+
+    let err = oauth_client.get_token(&details(1, 600), None).unwrap_err();
+    assert!(matches!(err, PollError::ExpiredToken));
+}
+
+#[test]
+fn cancel_stops_polling() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    pending_token_endpoint(&mut mock);
+
+    let details = details(5, 600);
+    let cancel = CancelFlag::new();
+    let poller = Poller::new(&details, None).with_cancel(cancel.clone());
+
+    let start = Instant::now();
+    let err = std::thread::scope(|s| {
+        let polling = s.spawn(|| oauth_client.poll(&details, poller));
+        std::thread::sleep(Duration::from_millis(300));
+        cancel.cancel();
+        polling.join().unwrap().unwrap_err()
+    });
+
+    assert!(matches!(err, PollError::Cancelled));
+    // Well before the 5 second interval ends
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn status_reports_are_throttled() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    let token = mock
+        .server
+        .mock("POST", "/token")
+        .with_status(400)
+        .with_body(r#"{ "error": "authorization_pending" }"#)
+        .expect_at_least(3)
+        .create();
+
+    let details = details(1, 4);
+    let (status_tx, status_rx) = std::sync::mpsc::channel();
+    let poller = Poller::new(&details, None).with_status(status_tx, Duration::from_secs(2));
+    oauth_client.poll(&details, poller).unwrap_err();
+    token.assert();
+
+    // About four polls, but a report at most every two seconds
+    let reports: Vec<Duration> = status_rx.iter().collect();
+    assert!((1..=2).contains(&reports.len()), "{:?}", reports);
+    assert!(reports.iter().all(|r| *r < Duration::from_secs(3)));
+}
+
+#[test]
+fn status_reports_can_be_disabled() {
+    let (mut mock, oauth_client) = Mock::builder().init(None);
+    pending_token_endpoint(&mut mock);
+
+    let details = details(1, 2);
+    let (status_tx, status_rx) = std::sync::mpsc::channel();
+    let poller = Poller::new(&details, None).with_status(status_tx, Duration::ZERO);
+    oauth_client.poll(&details, poller).unwrap_err();
+
+    assert_eq!(status_rx.iter().count(), 0);
+}
+
+#[test]
+fn waiting_message() {
+    let mut messages = Messages::default();
+    assert_eq!(
+        messages.waiting(Duration::from_secs(270)),
+        "Still waiting for sign-in, 4:30 left..."
+    );
+
+    messages.status_waiting = "{remaining} remaining".to_string();
+    assert_eq!(messages.waiting(Duration::from_secs(5)), "0:05 remaining");
+}
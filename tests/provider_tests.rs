@@ -0,0 +1,78 @@
+use pam_oauth2_device::config::Config;
+use pam_oauth2_device::provider::Provider;
+use serde_json::{json, Value};
+
+fn parse(mut fields: Value) -> Config {
+    fields["client_id"] = json!("test");
+    serde_json::from_value(fields).unwrap()
+}
+
+#[test]
+fn detects_azure_by_tenant() {
+    let config = parse(json!({
+        "oauth_token_url": "https://idp.example.com/token",
+        "tenant_id": "contoso",
+    }));
+    assert_eq!(Provider::detect(&config), Provider::Azure);
+    assert_eq!(
+        config.issuers(),
+        [
+            "https://login.microsoftonline.com/contoso/v2.0",
+            "https://sts.windows.net/contoso/",
+        ]
+    );
+    assert_eq!(
+        config.jwks_url().unwrap().as_str(),
+        "https://login.microsoftonline.com/contoso/discovery/v2.0/keys"
+    );
+}
+
+#[test]
+fn detects_azure_by_login_host() {
+    let config = parse(json!({
+        "oauth_token_url": "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+    }));
+    assert_eq!(Provider::detect(&config), Provider::Azure);
+    assert_eq!(
+        config.issuers(),
+        [
+            "https://login.microsoftonline.com/common/v2.0",
+            "https://sts.windows.net/common/",
+        ]
+    );
+}
+
+#[test]
+fn other_hosts_are_generic() {
+    for url in [
+        "https://keycloak.example.com/realms/main/protocol/openid-connect/token",
+        // Only the host counts, not the path
+        "https://idp.example.com/login.microsoftonline.com/token",
+        "https://login.microsoftonline.com.example.com/token",
+    ] {
+        let config = parse(json!({ "oauth_token_url": url }));
+        assert_eq!(Provider::detect(&config), Provider::Generic, "{}", url);
+        assert!(config.issuers().is_empty());
+        assert_eq!(config.jwks_url(), None);
+    }
+
+    let config = parse(json!({}));
+    assert_eq!(Provider::detect(&config), Provider::Generic);
+}
+
+#[test]
+fn configured_provider_wins() {
+    let config = parse(json!({
+        "oauth_token_url": "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+        "provider": "generic",
+    }));
+    assert_eq!(Provider::detect(&config), Provider::Azure);
+    assert_eq!(config.provider(), Provider::Generic);
+    assert!(config.issuers().is_empty());
+
+    let config = parse(json!({
+        "issuer": "https://issuer.example.com/",
+        "tenant_id": "contoso",
+    }));
+    assert_eq!(config.issuers(), ["https://issuer.example.com/"]);
+}
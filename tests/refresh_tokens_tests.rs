@@ -0,0 +1,206 @@
+mod utils;
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use jsonwebtoken::Algorithm;
+use mockito::Matcher;
+use oauth2::{Scope, TokenResponse};
+use pam_oauth2_device::oauth_device::{OAuthClient, RefreshRejected};
+use pam_oauth2_device::refresh_tokens::{RefreshTokenStore, StoredToken};
+use serde_json::json;
+use utils::{id_token_claims, sign_token, Mock};
+
+fn store_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-refresh-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn refreshing_client() -> (Mock, OAuthClient) {
+    Mock::builder().init_with(Some("openid"), |config| {
+        config.refresh_token_services = vec!["sudo".to_string()];
+    })
+}
+
+const DAY: i64 = 24 * 60 * 60;
+const NOW: i64 = 1_700_000_000;
+
+fn store(dir: &Path, client_id: &str) -> RefreshTokenStore {
+    RefreshTokenStore::new(dir, client_id, Duration::from_secs(30 * DAY as u64))
+}
+
+fn stored(refresh_token: &str) -> StoredToken {
+    StoredToken {
+        refresh_token: refresh_token.to_string(),
+        sub: Some("alice-sub".to_string()),
+    }
+}
+
+fn stored_files(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect()
+}
+
+#[test]
+fn encrypted_round_trip() {
+    let dir = store_dir("round-trip");
+    let store = store(&dir, "client");
+
+    assert_eq!(store.load("alice", "sudo", NOW).unwrap(), None);
+    store.save("alice", "sudo", &stored("refresh-secret"), NOW).unwrap();
+    assert_eq!(store.load("alice", "sudo", NOW + DAY).unwrap(), Some(stored("refresh-secret")));
+
+    let file = &stored_files(&dir)[0];
+    let contents = std::fs::read_to_string(file).unwrap();
+    assert!(!contents.contains("refresh-secret"));
+    assert!(!contents.contains("alice-sub"));
+    assert_eq!(
+        std::fs::metadata(file).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    store.remove("alice", "sudo").unwrap();
+    assert_eq!(store.load("alice", "sudo", NOW).unwrap(), None);
+}
+
+#[test]
+fn bound_to_user_service_and_client() {
+    let dir = store_dir("bound");
+    let store = store(&dir, "client");
+    store.save("alice", "sudo", &stored("alice-secret"), NOW).unwrap();
+    assert_eq!(store.load("bob", "sudo", NOW).unwrap(), None);
+    assert_eq!(store.load("alice", "su", NOW).unwrap(), None);
+
+    let alice = stored_files(&dir)[0].clone();
+    store.save("bob", "sudo", &stored("bob-secret"), NOW).unwrap();
+    let bob = stored_files(&dir).into_iter().find(|p| *p != alice).unwrap();
+    std::fs::copy(&alice, &bob).unwrap();
+    let err = store.load("bob", "sudo", NOW).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to decrypt refresh token of bob");
+    assert!(self::store(&dir, "other-client").load("alice", "sudo", NOW).is_err());
+}
+
+#[test]
+fn old_tokens_are_dropped() {
+    let dir = store_dir("age");
+    let store = store(&dir, "client");
+    store.save("alice", "sudo", &stored("old"), NOW).unwrap();
+    store.save("bob", "sudo", &stored("old"), NOW).unwrap();
+
+    assert_eq!(store.load("alice", "sudo", NOW + 30 * DAY).unwrap(), None);
+    assert_eq!(stored_files(&dir).len(), 1);
+    store.save("carol", "sudo", &stored("new"), NOW + 30 * DAY).unwrap();
+    assert_eq!(stored_files(&dir).len(), 1);
+    // Nor does setting the clock back extend them
+    assert_eq!(store.load("carol", "sudo", NOW).unwrap(), None);
+}
+
+#[test]
+fn accessible_file_is_refused() {
+    let dir = store_dir("mode");
+    let store = store(&dir, "client");
+    store.save("alice", "sudo", &stored("refresh-secret"), NOW).unwrap();
+
+    std::fs::set_permissions(&stored_files(&dir)[0], std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(store.load("alice", "sudo", NOW).is_err());
+}
+
+#[test]
+fn offline_access_is_requested() {
+    let (_mock, oauth_client) = refreshing_client();
+
+    assert_eq!(
+        oauth_client.scopes(),
+        &[Scope::new("openid".to_string()), Scope::new("offline_access".to_string())]
+    );
+}
+
+#[test]
+fn refreshed_token_is_verified() {
+    let (mut mock, oauth_client) = refreshing_client();
+    mock.http_jwks();
+    let id_token = sign_token(Algorithm::RS256, &id_token_claims(&mock, "test"));
+    let refresh = mock
+        .server
+        .mock("POST", "/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+            Matcher::UrlEncoded("refresh_token".into(), "old-refresh".into()),
+            Matcher::UrlEncoded("client_secret".into(), "test".into()),
+        ]))
+        .with_body(
+            json!({
+                "access_token": "access",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": "new-refresh",
+                "id_token": id_token,
+            })
+            .to_string(),
+        )
+        .create();
+
+    let token = oauth_client.refresh("old-refresh").unwrap();
+    let claims = oauth_client.verify(&token).unwrap();
+    assert_eq!(claims.username(), "test");
+    assert_eq!(token.refresh_token().unwrap().secret(), "new-refresh");
+    refresh.assert();
+}
+
+#[test]
+fn refreshed_token_fails_validation() {
+    let (mut mock, oauth_client) = refreshing_client();
+    mock.http_jwks();
+    let mut claims = id_token_claims(&mock, "test");
+    claims["aud"] = json!("someone-else");
+    let id_token = sign_token(Algorithm::RS256, &claims);
+    mock.server
+        .mock("POST", "/token")
+        .with_body(
+            json!({ "access_token": "access", "token_type": "Bearer", "id_token": id_token })
+                .to_string(),
+        )
+        .create();
+
+    let token = oauth_client.refresh("old-refresh").unwrap();
+    assert!(oauth_client.verify(&token).is_err());
+}
+
+#[test]
+fn disabled_user_is_refused() {
+    let (mut mock, oauth_client) = refreshing_client();
+    mock.server
+        .mock("POST", "/token")
+        .with_status(400)
+        .with_body(r#"{ "error": "invalid_grant", "error_description": "User account is disabled" }"#)
+        .create();
+
+    let err = oauth_client.refresh("old-refresh").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r#"Refresh token rejected (400 Bad Request): { "error": "invalid_grant", "error_description": "User account is disabled" }"#
+    );
+    assert!(err.downcast_ref::<RefreshRejected>().unwrap().is_invalid_grant());
+}
+
+#[test]
+fn outage_is_not_invalid_grant() {
+    let (mut mock, oauth_client) = refreshing_client();
+    mock.server
+        .mock("POST", "/token")
+        .with_status(503)
+        .with_body("Service Unavailable")
+        .create();
+
+    let err = oauth_client.refresh("old-refresh").unwrap_err();
+    assert!(!err.downcast_ref::<RefreshRejected>().unwrap().is_invalid_grant());
+}
@@ -0,0 +1,112 @@
+mod utils;
+
+use mockito::Matcher;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use serde_json::{json, Value};
+use url::Url;
+use utils::{mock_config, Mock};
+
+fn client(mock: &Mock, revocation_url: Option<&str>) -> OAuthClient {
+    let mut config = mock_config(&mock.server.url(), None);
+    config.revoke_on_close = true;
+    config.revocation_url = revocation_url.map(|path| Url::parse(&format!("{}{}", mock.server.url(), path)).unwrap());
+    OAuthClient::new(&config).unwrap()
+}
+
+#[test]
+fn revoke_refresh_token() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client(&mock, Some("/revoke"));
+    let revoke = mock
+        .server
+        .mock("POST", "/revoke")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("token".into(), "refresh".into()),
+            Matcher::UrlEncoded("token_type_hint".into(), "refresh_token".into()),
+            Matcher::UrlEncoded("client_id".into(), "test".into()),
+            Matcher::UrlEncoded("client_secret".into(), "test".into()),
+        ]))
+        .with_status(200)
+        .create();
+
+    oauth_client.revoke("refresh", "refresh_token").unwrap();
+    revoke.assert();
+}
+
+#[test]
+fn revocation_error() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let oauth_client = client(&mock, Some("/revoke"));
+    mock.server
+        .mock("POST", "/revoke")
+        .with_status(400)
+        .with_body(r#"{ "error": "unsupported_token_type" }"#)
+        .create();
+
+    let err = oauth_client.revoke("access", "access_token").unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        format!(
+            "Revocation request failed: HTTP status client error (400 Bad Request) for url ({}/revoke)",
+            mock.server.url()
+        )
+    );
+}
+
+/// Serves a discovery document for the mock issuer, with `endpoints` on top
+/// of the issuer.
+fn well_known(mock: &mut Mock, endpoints: Value) -> mockito::Mock {
+    let mut body = json!({ "issuer": mock.server.url() });
+    for (k, v) in endpoints.as_object().unwrap() {
+        body[k] = json!(format!("{}{}", mock.server.url(), v.as_str().unwrap()));
+    }
+    mock.server
+        .mock("GET", "/.well-known/openid-configuration")
+        .with_status(200)
+        .with_body(body.to_string())
+        .expect(1)
+        .create()
+}
+
+#[test]
+fn revocation_url_is_discovered() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let discovery = well_known(&mut mock, json!({ "revocation_endpoint": "/revoke" }));
+    let oauth_client = client(&mock, None);
+
+    discovery.assert();
+    assert_eq!(
+        oauth_client.config().revocation_url.as_ref().unwrap().as_str(),
+        format!("{}/revoke", mock.server.url())
+    );
+}
+
+#[test]
+fn no_revocation_url() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let discovery = well_known(&mut mock, json!({}));
+    let oauth_client = client(&mock, None);
+    discovery.assert();
+
+    let err = oauth_client.revoke("access", "access_token").unwrap_err();
+    assert_eq!(err.to_string(), "No revocation_url configured or discovered");
+}
+
+#[test]
+fn close_uses_cached_discovery_only() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let discovery = well_known(&mut mock, json!({ "revocation_endpoint": "/revoke" }));
+    let mut config = mock_config(&mock.server.url(), None);
+    config.revoke_on_close = true;
+
+    let offline = OAuthClient::without_discovery(&config).unwrap();
+    assert!(offline.config().revocation_url.is_none());
+
+    OAuthClient::new(&config).unwrap();
+    let offline = OAuthClient::without_discovery(&config).unwrap();
+    assert_eq!(
+        offline.config().revocation_url.as_ref().unwrap().as_str(),
+        format!("{}/revoke", mock.server.url())
+    );
+    discovery.assert();
+}
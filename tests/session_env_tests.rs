@@ -0,0 +1,95 @@
+mod utils;
+
+use pam_oauth2_device::claims::VerifiedClaims;
+use pam_oauth2_device::session_env::SessionEnv;
+use serde_json::{json, Value};
+use utils::{mock_config, verified_claims, Mock};
+
+fn verified(extra: Value) -> VerifiedClaims {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    verified_claims(&mock_config(&mock.server.url(), None), extra).unwrap()
+}
+
+fn exports(session_env: Value, claims: &VerifiedClaims) -> Vec<(String, String)> {
+    serde_json::from_value::<SessionEnv>(session_env)
+        .unwrap()
+        .exports(claims)
+}
+
+fn pair(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+#[test]
+fn default_and_renamed_variables() {
+    let claims = verified(json!({
+        "upn": "test@example.com",
+        "email": "test@example.com",
+        "groups": ["ops", "dba"],
+    }));
+    let exp = claims.exp().unwrap().to_string();
+
+    assert_eq!(
+        exports(
+            json!({
+                "claims": ["sub", "upn", "email", "groups", "exp"],
+                "names": { "exp": "OAUTH2_TOKEN_EXP" },
+            }),
+            &claims
+        ),
+        vec![
+            pair("OAUTH2_SUB", "test-sub"),
+            pair("OAUTH2_UPN", "test@example.com"),
+            pair("OAUTH2_EMAIL", "test@example.com"),
+            pair("OAUTH2_GROUPS", "ops,dba"),
+            pair("OAUTH2_TOKEN_EXP", &exp),
+        ]
+    );
+}
+
+#[test]
+fn prefix_and_pointer() {
+    let claims = verified(json!({ "realm_access": { "roles": ["ssh", "admin"] } }));
+
+    assert_eq!(
+        exports(
+            json!({ "claims": ["/realm_access/roles", "missing"], "prefix": "IDP_" }),
+            &claims
+        ),
+        vec![pair("IDP_REALM_ACCESS_ROLES", "ssh,admin")]
+    );
+}
+
+#[test]
+fn values_cannot_inject() {
+    let claims = verified(json!({
+        "name": "Mallory\nLD_PRELOAD=/tmp/evil.so\u{0}",
+        "nested": [["a"], { "b": 1 }, "c"],
+        "profile": { "x": 1 },
+    }));
+
+    assert_eq!(
+        exports(json!({ "claims": ["name", "nested", "profile"] }), &claims),
+        vec![
+            pair("OAUTH2_NAME", "MalloryLD_PRELOAD=/tmp/evil.so"),
+            pair("OAUTH2_NESTED", "c"),
+        ]
+    );
+}
+
+#[test]
+fn invalid_variable_names_are_skipped() {
+    let claims = verified(json!({ "email": "test@example.com" }));
+
+    assert_eq!(
+        exports(
+            json!({
+                "claims": ["sub", "email"],
+                "names": { "sub": "BAD=NAME", "email": "1EMAIL" },
+            }),
+            &claims
+        ),
+        vec![]
+    );
+}
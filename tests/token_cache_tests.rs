@@ -0,0 +1,125 @@
+mod utils;
+
+use std::os::unix::fs::PermissionsExt;
+
+use jsonwebtoken::Algorithm;
+use pam_oauth2_device::oauth_device::DeviceTokenResponse;
+use pam_oauth2_device::token_cache::{CachedTokens, TokenCache};
+use serde_json::{json, Value};
+use utils::{id_token_claims, mock_config, sign_token, verified_claims, Mock};
+
+fn cached_tokens(token: Value) -> (String, CachedTokens) {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+    let config = mock_config(&mock.server.url(), None);
+    let claims = verified_claims(&config, json!({})).unwrap();
+
+    let id_token = sign_token(Algorithm::RS256, &id_token_claims(&mock, "test"));
+    let mut token = token;
+    token["id_token"] = json!(id_token);
+    let token: DeviceTokenResponse = serde_json::from_value(token).unwrap();
+    (id_token, CachedTokens::new(&token, &claims, &config, 1_700_000_000))
+}
+
+fn cache(name: &str) -> TokenCache {
+    let dir = std::env::temp_dir().join(format!(
+        "pam_oauth2_device-token-cache-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    let template = format!("{}/{{user}}-{{uid}}.json", dir.display());
+    TokenCache::new(&template, "test", users::get_current_uid(), users::get_current_gid()).unwrap()
+}
+
+#[test]
+fn file_format() {
+    let (id_token, tokens) = cached_tokens(json!({
+        "access_token": "access",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+        "refresh_token": "refresh",
+        "scope": "openid profile",
+    }));
+
+    let value = serde_json::to_value(&tokens).unwrap();
+    assert_eq!(value["version"], 1);
+    assert_eq!(value["client_id"], "test");
+    assert_eq!(value["username"], "test");
+    assert_eq!(value["token_type"], "Bearer");
+    assert_eq!(value["access_token"], "access");
+    assert_eq!(value["expires_at"], 1_700_003_600);
+    assert_eq!(value["refresh_token"], "refresh");
+    assert_eq!(value["id_token"], id_token.as_str());
+    assert_eq!(value["scope"], "openid profile");
+    assert!(value["token_endpoint"].as_str().unwrap().ends_with("/token"));
+}
+
+#[test]
+fn missing_fields_are_left_out() {
+    let (_, tokens) = cached_tokens(json!({ "access_token": "access", "token_type": "Bearer" }));
+
+    let value = serde_json::to_value(&tokens).unwrap();
+    assert!(value.get("refresh_token").is_none());
+    assert!(value.get("expires_at").is_none());
+    assert!(value.get("scope").is_none());
+    assert!(value.get("refresh_token_stored").is_none());
+}
+
+#[test]
+fn write_and_remove() {
+    let (_, tokens) = cached_tokens(json!({ "access_token": "access", "token_type": "Bearer" }));
+    let cache = cache("write");
+    assert!(cache
+        .path()
+        .ends_with(format!("test-{}.json", users::get_current_uid())));
+
+    cache.write(&tokens).unwrap();
+    cache.write(&tokens).unwrap();
+    let file = std::fs::metadata(cache.path()).unwrap();
+    let dir = std::fs::metadata(cache.path().parent().unwrap()).unwrap();
+    assert_eq!(file.permissions().mode() & 0o777, 0o600);
+    assert_eq!(dir.permissions().mode() & 0o777, 0o700);
+    let written: CachedTokens =
+        serde_json::from_reader(std::fs::File::open(cache.path()).unwrap()).unwrap();
+    assert_eq!(written, tokens);
+    assert_eq!(std::fs::read_dir(cache.path().parent().unwrap()).unwrap().count(), 1);
+
+    cache.remove().unwrap();
+    assert!(!cache.path().exists());
+    cache.remove().unwrap();
+}
+
+#[test]
+fn missing_parent_is_not_created() {
+    let (_, tokens) = cached_tokens(json!({ "access_token": "access", "token_type": "Bearer" }));
+    let dir = std::env::temp_dir().join(format!("pam_oauth2_device-no-parent-{}", std::process::id()));
+    let template = format!("{}/oauth2/tokens.json", dir.display());
+    let cache = TokenCache::new(&template, "test", users::get_current_uid(), users::get_current_gid()).unwrap();
+
+    assert!(cache.write(&tokens).is_err());
+    assert!(!dir.exists());
+}
+
+#[test]
+fn username_cannot_escape() {
+    assert!(TokenCache::new("/run/user/{uid}/oauth2/{user}.json", "../root", 0, 0).is_err());
+}
+
+#[test]
+fn symlinked_directory_is_refused() {
+    let (_, tokens) = cached_tokens(json!({ "access_token": "access", "token_type": "Bearer" }));
+    let cache = cache("symlink");
+    let dir = cache.path().parent().unwrap().to_path_buf();
+    let target = dir.with_extension("target");
+    let _ = std::fs::remove_dir_all(&target);
+    std::fs::create_dir(&target).unwrap();
+    std::os::unix::fs::symlink(&target, &dir).unwrap();
+    let victim = target.join(cache.path().file_name().unwrap());
+    std::fs::write(&victim, "keep").unwrap();
+
+    assert!(cache.write(&tokens).is_err());
+    assert!(cache.remove().is_err());
+    assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+    assert_eq!(std::fs::read_dir(&target).unwrap().count(), 1);
+}
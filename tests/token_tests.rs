@@ -39,11 +39,11 @@ fn token_basic_err() {
     let token = oauth_client.get_token(&device_details, None);
     assert!(token.is_err());
 
-    let _ = token.map_err(|err| TestLogger::handle_error(err, "Failed to recive user token"));
+    let _ = token.map_err(|err| TestLogger::handle_error(err.into(), "Failed to recive user token"));
 
     assert_eq!(
         logger.msg(),
-        "Failed to recive user token\n    caused by: Server returned error response"
+        "Failed to recive user token\n    caused by: Authorization request was denied: access_denied: Authorization for user is still pending."
     );
 }
 
@@ -59,10 +59,10 @@ fn token_other_err() {
     let token = oauth_client.get_token(&device_details, None);
     assert!(token.is_err());
 
-    let _ = token.map_err(|err| TestLogger::handle_error(err, "Failed to recive user token"));
+    let _ = token.map_err(|err| TestLogger::handle_error(err.into(), "Failed to recive user token"));
 
     assert_eq!(
         logger.msg(),
-        "Failed to recive user token\n    caused by: Server returned error response"
+        "Failed to recive user token\n    caused by: Authorization request was denied: access_denied: Authorization for user is still pending."
     );
 }
@@ -0,0 +1,131 @@
+mod utils;
+
+use jsonwebtoken::Algorithm;
+use oauth2::AccessToken;
+use pam_oauth2_device::config::TokenValidation;
+use pam_oauth2_device::oauth_device::OAuthClient;
+use pam_oauth2_device::username::{transform, UsernameTransform};
+use serde_json::json;
+use utils::{id_token_claims, mock_config, sign_token, Mock};
+
+fn transforms(value: serde_json::Value) -> Vec<UsernameTransform> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn upn_to_local_name() {
+    let t = transforms(json!([
+        { "type": "strip_domain" },
+        { "type": "lowercase" },
+        { "type": "allowed_characters", "allowed": "a-z0-9._-" }
+    ]));
+
+    assert_eq!(transform("JDoe@corp.example.com", &t).unwrap(), "jdoe");
+    assert_eq!(transform("jdoe", &t).unwrap(), "jdoe");
+}
+
+#[test]
+fn b2b_guest_upn() {
+    let t = transforms(json!([
+        { "type": "regex_replace", "pattern": "#EXT#@.*$", "replacement": "" },
+        { "type": "regex_replace", "pattern": "_([^_]*)$", "replacement": "@$1" },
+        { "type": "strip_domain" }
+    ]));
+
+    assert_eq!(
+        transform("jdoe_partner.example.com#EXT#@corp.onmicrosoft.com", &t).unwrap(),
+        "jdoe"
+    );
+}
+
+#[test]
+fn disallowed_characters_fail() {
+    let t = transforms(json!([{ "type": "allowed_characters", "allowed": "a-z0-9._-" }]));
+
+    assert_eq!(
+        transform("jdoe;rm -rf", &t).unwrap_err().to_string(),
+        "Username 'jdoe;rm -rf' contains characters outside of [a-z0-9._-]"
+    );
+}
+
+#[test]
+fn empty_username_fails() {
+    let t = transforms(json!([{ "type": "regex_replace", "pattern": ".*" }]));
+
+    assert_eq!(
+        transform("jdoe", &t).unwrap_err().to_string(),
+        "Username derived from 'jdoe' is empty"
+    );
+}
+
+#[test]
+fn configured_claim_is_used() {
+    let (mut mock, _) = Mock::builder().init(None);
+    mock.http_jwks();
+
+    let mut config = mock_config(&mock.server.url(), None);
+    config.username_claim = Some(vec!["upn".to_string()]);
+    config.username_transforms = transforms(json!([{ "type": "strip_domain" }]));
+    let oauth_client = OAuthClient::new(&config).unwrap();
+
+    let mut claims = id_token_claims(&mock, "ignored");
+    claims["upn"] = json!("jdoe@corp.example.com");
+    let claims = oauth_client
+        .validate_token_claims(&AccessToken::new(sign_token(Algorithm::RS256, &claims)))
+        .unwrap();
+    assert_eq!(claims.username(), "jdoe");
+
+    let claims = id_token_claims(&mock, "no-upn");
+    let err = oauth_client
+        .validate_token_claims(&AccessToken::new(sign_token(Algorithm::RS256, &claims)))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "No username provided in token");
+}
+
+#[test]
+fn introspection_prefers_configured_claim() {
+    let (mut mock, _) = Mock::builder().init(None);
+    let mut config = mock_config(&mock.server.url(), Some("openid"));
+    config.token_validation = TokenValidation::Introspection;
+    config.username_claim = Some(vec!["upn".to_string()]);
+    config.username_transforms = transforms(json!([{ "type": "strip_domain" }]));
+    let oauth_client = OAuthClient::new(&config).unwrap();
+    let exp = (chrono::Utc::now() + chrono::Duration::seconds(3600)).timestamp();
+    let mut introspect = |body: serde_json::Value| {
+        let _m = mock
+            .server
+            .mock("POST", "/introspect")
+            .with_body(body.to_string())
+            .create();
+        oauth_client
+            .introspect(&AccessToken::new("token".to_string()))
+            .unwrap()
+    };
+
+    // No `username` member at all
+    let token = introspect(json!({
+        "active": true, "scope": "openid", "exp": exp, "upn": "jdoe@corp.example.com"
+    }));
+    assert!(oauth_client.validate_token(&token, "jdoe"));
+
+    let token = introspect(json!({
+        "active": true, "scope": "openid", "exp": exp,
+        "upn": "jdoe@corp.example.com", "username": "someone-else"
+    }));
+    assert!(oauth_client.validate_token(&token, "jdoe"));
+
+    // No implicit fallback once a claim is configured
+    let token = introspect(json!({
+        "active": true, "scope": "openid", "exp": exp, "username": "jdoe@corp.example.com"
+    }));
+    assert!(!oauth_client.validate_token(&token, "jdoe"));
+}
+
+#[test]
+fn invalid_character_class_is_a_config_error() {
+    let err = serde_json::from_value::<Vec<UsernameTransform>>(json!([
+        { "type": "allowed_characters", "allowed": "z-a" }
+    ]))
+    .unwrap_err();
+    assert!(err.to_string().contains("invalid character class range"), "{}", err);
+}
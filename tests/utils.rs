@@ -1,8 +1,12 @@
 use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use mockito::{Server, ServerGuard};
-use pam_oauth2_device::config::{Config, Messages};
+use oauth2::AccessToken;
+use pam_oauth2_device::claims::VerifiedClaims;
+use pam_oauth2_device::config::Config;
 use pam_oauth2_device::oauth_device::OAuthClient;
-use url::Url;
+use serde_json::{json, Value};
+use tempfile::TempDir;
 
 macro_rules! builder_setter {
     ($field:ident, optional $type:ty) => {
@@ -25,6 +29,7 @@ pub(crate) struct Mock {
     scope: Option<String>,
     active: bool,
     exp: Option<DateTime<Utc>>,
+    _config: Option<MockConfig>,
 }
 
 #[allow(dead_code)]
@@ -36,6 +41,7 @@ impl Mock {
             scope: None,
             active: true,
             exp: Some(chrono::Utc::now() + Duration::seconds(3600)),
+            _config: None,
         })
     }
 }
@@ -50,7 +56,18 @@ impl MockBuilder {
     builder_setter!(exp, optional DateTime<Utc>);
 
     pub(crate) fn init(self, pam_scopes: Option<&str>) -> (Mock, OAuthClient) {
-        let config = mock_config(&self.0.server.url(), pam_scopes);
+        self.init_with(pam_scopes, |_| {})
+    }
+
+    /// Like `init`, with the config changed by `configure` first. The mock
+    /// keeps the config, so its cache directory lives as long as the mock.
+    pub(crate) fn init_with(
+        self,
+        pam_scopes: Option<&str>,
+        configure: impl FnOnce(&mut Config),
+    ) -> (Mock, OAuthClient) {
+        let mut config = mock_config(&self.0.server.url(), pam_scopes);
+        configure(&mut config);
         let oauth_client = OAuthClient::new(&config)
             .unwrap_or_else(|err| panic!("Failed to create OAuth client: {}", err));
         let mock = Mock {
@@ -59,30 +76,143 @@ impl MockBuilder {
             scope: self.0.scope,
             active: self.0.active,
             exp: self.0.exp,
+            _config: Some(config),
         };
         (mock, oauth_client)
     }
 }
 
 #[allow(dead_code)]
-pub(crate) fn mock_config(url: &String, scope: Option<&str>) -> Config {
-    let scope = scope.map(|s| s.to_owned());
-    Config {
-        client_id: "test".to_string(),
-        client_secret: "test".to_string(),
-        oauth_auth_url: Url::parse(&format!("{}/{}", url, "auth")).unwrap(),
-        oauth_device_url: Url::parse(&format!("{}/{}", url, "device")).unwrap(),
-        oauth_token_url: Url::parse(&format!("{}/{}", url, "token")).unwrap(),
-        oauth_token_introspect_url: Url::parse(&format!("{}/{}", url, "introspect")).unwrap(),
-        oauth_device_token_polling_timeout: None,
-        scopes: scope.unwrap_or_default(),
-        qr_enabled: false,
-        messages: Messages::default(),
+/// A config pointing at a mock server, with its own cache directory that is
+/// removed when the config is dropped.
+pub(crate) struct MockConfig {
+    pub config: Config,
+    _cache_dir: TempDir,
+}
+
+impl std::ops::Deref for MockConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl std::ops::DerefMut for MockConfig {
+    fn deref_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+}
+
+pub(crate) fn mock_config(url: &String, scope: Option<&str>) -> MockConfig {
+    let cache_dir = tempfile::Builder::new()
+        .prefix("pam_oauth2_device-test-")
+        .tempdir()
+        .unwrap();
+    let config = serde_json::from_value(json!({
+        "client_id": "test",
+        "client_secret": "test",
+        "oauth_auth_url": format!("{}/{}", url, "auth"),
+        "oauth_device_url": format!("{}/{}", url, "device"),
+        "oauth_token_url": format!("{}/{}", url, "token"),
+        "oauth_token_introspect_url": format!("{}/{}", url, "introspect"),
+        "jwks_url": format!("{}/{}", url, "jwks"),
+        "issuer": url,
+        "cache_dir": cache_dir.path(),
+        "scopes": scope.unwrap_or_default(),
+        "qr_enabled": false,
+    }))
+    .unwrap_or_else(|err| panic!("Failed to build mock config: {}", err));
+    MockConfig {
+        config,
+        _cache_dir: cache_dir,
     }
 }
 
+#[allow(dead_code)]
+pub(crate) fn fixture(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Signs `claims` with one of the keys published by `Mock::http_jwks`.
+#[allow(dead_code)]
+pub(crate) fn sign_token(alg: Algorithm, claims: &Value) -> String {
+    let (kid, key) = match alg {
+        Algorithm::ES256 => (
+            "ec-key",
+            EncodingKey::from_ec_pem(include_bytes!("fixtures/ec_private.pem")).unwrap(),
+        ),
+        Algorithm::EdDSA => (
+            "ed-key",
+            EncodingKey::from_ed_pem(include_bytes!("fixtures/ed_private.pem")).unwrap(),
+        ),
+        _ => (
+            "rsa-key",
+            EncodingKey::from_rsa_pem(include_bytes!("fixtures/rsa_private.pem")).unwrap(),
+        ),
+    };
+    let mut header = Header::new(alg);
+    header.kid = Some(kid.to_string());
+    encode(&header, claims, &key).unwrap()
+}
+
+/// Claims of a valid id_token for the mock server.
+#[allow(dead_code)]
+pub(crate) fn id_token_claims(mock: &Mock, username: &str) -> Value {
+    token_claims(&mock.server.url(), username)
+}
+
+/// Validates an id_token for `test`, with `extra` merged into its claims,
+/// with a client for `config`. The mock server `config` points at must
+/// serve `Mock::http_jwks`.
+#[allow(dead_code)]
+pub(crate) fn verified_claims(config: &Config, extra: Value) -> anyhow::Result<VerifiedClaims> {
+    let mut claims = token_claims(&config.issuer[0], "test");
+    for (k, v) in extra.as_object().unwrap() {
+        claims[k] = v.clone();
+    }
+    let id_token = sign_token(Algorithm::RS256, &claims);
+    OAuthClient::new(config)?.validate_token_claims(&AccessToken::new(id_token))
+}
+
+fn token_claims(issuer: &str, username: &str) -> Value {
+    json!({
+        "iss": issuer,
+        "aud": "test",
+        "sub": format!("{}-sub", username),
+        "exp": (Utc::now() + Duration::seconds(3600)).timestamp(),
+        "preferred_username": username,
+    })
+}
+
 #[allow(dead_code)]
 impl Mock {
+    pub(crate) fn http_jwks(&mut self) {
+        self.server
+            .mock("GET", "/jwks")
+            .with_status(200)
+            .with_body(include_str!("fixtures/jwks.json"))
+            .create();
+    }
+
+    pub(crate) fn http_token_with_id_token(&mut self, id_token: &str) {
+        self.server
+            .mock("POST", "/token")
+            .with_status(200)
+            .with_body(
+                json!({
+                    "access_token": "mocking_access_token",
+                    "id_token": id_token,
+                    "token_type": "Bearer",
+                    "expires_in": 3600
+                })
+                .to_string(),
+            )
+            .create();
+    }
+
     pub(crate) fn http_device_basic(&mut self) {
         self.server
             .mock("POST", "/device")
@@ -175,14 +305,11 @@ impl Mock {
             }}"#,
                 self.active, scope, username, exp
             ),
-            _ => {
-                format!(
-                    r#"{{
+            _ => r#"{
         "error": "invalid_client",
         "error_description": "This client authentication was invalid"
-            }}"#
-                )
-            }
+            }"#
+            .to_string(),
         };
         self.server
             .mock("POST", "/introspect")